@@ -0,0 +1,296 @@
+//! Benchmarks of the renderer, run with `--bench` argument
+//!
+//! There is no benchmark harness, every benchmark times its
+//! variants over a number of frames and prints the results
+//!
+
+use crate::draw;
+use crate::draw_3d::{Mesh, Triangle3D, Vec3D};
+use crate::framebuffer::{Antialiasing, Filter, Framebuffer, RenderTarget};
+use crate::pipeline::{Pipeline, RenderMode};
+use crate::shader::{FlatShader, SceneUniforms};
+use crate::simd::{self, Backend};
+use crate::transform::{self, Mat4x4};
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::render::{TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
+use std::hint::black_box;
+use std::thread;
+use std::time::Instant;
+
+/// Frames every variant is timed over
+///
+const FRAMES: u32 = 100;
+
+/// Runs all benchmarks
+///
+pub fn run(canvas: &mut WindowCanvas, creator: &TextureCreator<WindowContext>) {
+    present(canvas, creator);
+    rasterize(canvas);
+    modes(canvas);
+    vertex();
+    simd();
+}
+
+/// Average time of `frame` in milliseconds
+///
+fn time<F: FnMut()>(mut frame: F) -> f64 {
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        frame();
+    }
+    start.elapsed().as_secs_f64() * 1000.0 / FRAMES as f64
+}
+
+/// Prints timings relative to the first (baseline) variant
+///
+fn report(name: &str, results: &[(&str, f64)]) {
+    println!("{} ({} frames)", name, FRAMES);
+    for (variant, ms) in results {
+        println!(
+            "  {:<24} {:>9.3} ms/frame {:>7.2}x",
+            variant,
+            ms,
+            results[0].1 / ms
+        );
+    }
+}
+
+/// Presenting full window image point by point
+/// vs uploading it through streaming texture
+///
+fn present(canvas: &mut WindowCanvas, creator: &TextureCreator<WindowContext>) {
+    let (width, height) = canvas.output_size().unwrap();
+    let (width, height) = (width as i32, height as i32);
+
+    let mut framebuffer = Framebuffer::new(width, height, Antialiasing::None);
+    // Gradient, so SDL can not batch points of the same color
+    for y in 0..height {
+        for x in 0..width {
+            let color = Color::RGB(x as u8, y as u8, (x + y) as u8);
+            framebuffer.set_sample(x, y, 0, color, 0.0);
+        }
+    }
+
+    let points = time(|| {
+        for y in 0..height {
+            for x in 0..width {
+                draw::pixel(Point::new(x, y), framebuffer.resolve(x, y), canvas);
+            }
+        }
+        canvas.present();
+    });
+
+    let mut texture = framebuffer.create_texture(creator, Filter::Nearest);
+    let streaming = time(|| {
+        framebuffer.present(canvas, &mut texture);
+        canvas.present();
+    });
+
+    report(
+        "present",
+        &[("draw_point", points), ("streaming texture", streaming)],
+    );
+}
+
+/// Wavy `n` x `n` grid of quads filling the view of camera
+/// looking down the z axis from origin
+///
+pub fn grid_mesh(n: usize) -> Mesh {
+    let point = |i: usize, j: usize| {
+        let (x, y) = (
+            i as f32 / n as f32 * 4.0 - 2.0,
+            j as f32 / n as f32 * 2.0 - 1.0,
+        );
+        Vec3D::new(x, y, 1.2 + 0.1 * (x * 7.0).sin() * (y * 5.0).cos())
+    };
+
+    let mut tris = Vec::with_capacity(n * n * 2);
+    for j in 0..n {
+        for i in 0..n {
+            let (p00, p10) = (point(i, j), point(i + 1, j));
+            let (p01, p11) = (point(i, j + 1), point(i + 1, j + 1));
+            tris.push(Triangle3D::new(p11, p10, p00));
+            tris.push(Triangle3D::new(p01, p11, p00));
+        }
+    }
+    Mesh::new(tris)
+}
+
+/// Single-threaded vs tiled multithreaded rasterization,
+/// equality of the images is checked by tests in `pipeline`
+///
+fn rasterize(canvas: &mut WindowCanvas) {
+    let (width, height) = canvas.output_size().unwrap();
+    let (width, height) = (width as i32, height as i32);
+
+    let mesh = grid_mesh(700);
+    let uniforms = SceneUniforms::new(
+        Mat4x4::id(),
+        Mat4x4::id(),
+        Mat4x4::mat_proj(90.0, height as f32 / width as f32, 1000.0, 0.1),
+        Vec3D::new(0.3, -0.4, 1.0).normalize(),
+    );
+    let mut results = Vec::new();
+
+    let threads = Pipeline::available_threads();
+    for &(variant, threads) in &[("1 thread", 1), ("tiled, all threads", threads)] {
+        let mut pipeline = Pipeline::new();
+        pipeline.threads = threads;
+        let mut framebuffer = Framebuffer::new(width, height, Antialiasing::None);
+
+        let ms = time(|| {
+            framebuffer.clear(Color::RGB(0, 0, 0));
+            pipeline.draw_mesh(&mesh, &uniforms, &FlatShader, &FlatShader, &mut framebuffer);
+        });
+        results.push((variant, ms));
+    }
+
+    report(
+        &format!("rasterize {} triangles", mesh.tris.len()),
+        &results,
+    );
+}
+
+/// Rendering the same mesh in every render mode
+///
+fn modes(canvas: &mut WindowCanvas) {
+    let (width, height) = canvas.output_size().unwrap();
+    let (width, height) = (width as i32, height as i32);
+
+    let mesh = grid_mesh(200);
+    let uniforms = SceneUniforms::new(
+        Mat4x4::id(),
+        Mat4x4::id(),
+        Mat4x4::mat_proj(90.0, height as f32 / width as f32, 1000.0, 0.1),
+        Vec3D::new(0.3, -0.4, 1.0).normalize(),
+    );
+    let mut pipeline = Pipeline::new();
+    let mut framebuffer = Framebuffer::new(width, height, Antialiasing::None);
+    let mut names = Vec::new();
+    let mut results = Vec::new();
+
+    let mut mode = RenderMode::Solid;
+    loop {
+        pipeline.mode = mode;
+        let ms = time(|| {
+            framebuffer.clear(Color::RGB(0, 0, 0));
+            pipeline.draw_mesh(&mesh, &uniforms, &FlatShader, &FlatShader, &mut framebuffer);
+        });
+        names.push(format!("{:?}", mode));
+        results.push(ms);
+
+        mode = mode.next();
+        if mode == RenderMode::Solid {
+            break;
+        }
+    }
+
+    let labeled: Vec<(&str, f64)> = names
+        .iter()
+        .zip(results)
+        .map(|(name, ms)| (name.as_str(), ms))
+        .collect();
+    report(
+        &format!("render modes {} triangles", mesh.tris.len()),
+        &labeled,
+    );
+}
+
+/// World, view and projection applied one by one vs precomposed
+/// world * view * projection, serially and in parallel chunks
+///
+fn vertex() {
+    let mesh = grid_mesh(700);
+    let vertices: Vec<Vec3D> = mesh.tris.iter().flat_map(|tri| tri.p.to_vec()).collect();
+    let uniforms = SceneUniforms::new(
+        Mat4x4::mat_rot_axis(&Vec3D::new(1.0, 1.0, 0.0).normalize(), &0.3),
+        Mat4x4::mat_trans(0.5, -0.2, 3.0),
+        Mat4x4::mat_proj(90.0, 0.5, 1000.0, 0.1),
+        Vec3D::new(0.0, 0.0, 1.0),
+    );
+    let threads = Pipeline::available_threads();
+    let chunk_size = vertices.len().div_ceil(threads);
+
+    let per_stage = time(|| {
+        for v in &vertices {
+            let world = transform::mult_matrix_vector(v, &uniforms.world);
+            let view = transform::mult_matrix_vector(&world, &uniforms.view);
+            let projected = transform::mult_matrix_vector(&view, &uniforms.proj);
+            black_box(projected);
+        }
+    });
+    let precomposed = time(|| {
+        for v in &vertices {
+            black_box(uniforms.to_clip(v));
+        }
+    });
+    let parallel = time(|| {
+        thread::scope(|scope| {
+            for chunk in vertices.chunks(chunk_size) {
+                let uniforms = &uniforms;
+                scope.spawn(move || {
+                    for v in chunk {
+                        black_box(uniforms.to_clip(v));
+                    }
+                });
+            }
+        });
+    });
+
+    report(
+        &format!("transform {} vertices", vertices.len()),
+        &[
+            ("per stage", per_stage),
+            ("precomposed", precomposed),
+            ("precomposed, all threads", parallel),
+        ],
+    );
+}
+
+/// Scalar vs SIMD matrix operations, equality of
+/// the results is checked by tests in `simd`
+///
+fn simd() {
+    let mesh = grid_mesh(700);
+    let points: Vec<Vec3D> = mesh.tris.iter().flat_map(|tri| tri.p.to_vec()).collect();
+    let a = Mat4x4::mat_rot_axis(&Vec3D::new(1.0, 1.0, 0.0).normalize(), &0.3);
+    let b = Mat4x4::mat_trans(0.5, -0.2, 3.0) * Mat4x4::mat_proj(90.0, 0.5, 1000.0, 0.1);
+    let backends = Backend::supported();
+
+    let mut points_results = Vec::new();
+    let mut mul_results = Vec::new();
+
+    for &backend in &backends {
+        let ms = time(|| {
+            black_box(simd::transform_points(backend, &points, &b));
+        });
+        points_results.push((backend, ms));
+
+        // Rotating keeps values bounded, repeated projection
+        // would overflow to inf and NaN within a few products
+        let ms = time(|| {
+            let mut m = b;
+            for _ in 0..100_000 {
+                m = simd::mat_mul(backend, &black_box(m), &a);
+            }
+            black_box(m);
+        });
+        mul_results.push((backend, ms));
+    }
+
+    let names: Vec<String> = backends.iter().map(|b| format!("{:?}", b)).collect();
+    let labeled = |results: &[(Backend, f64)]| -> Vec<(&str, f64)> {
+        names
+            .iter()
+            .zip(results)
+            .map(|(name, &(_, ms))| (name.as_str(), ms))
+            .collect()
+    };
+    report(
+        &format!("transform_points {} points", points.len()),
+        &labeled(&points_results),
+    );
+    report("mat_mul 100000 products", &labeled(&mul_results));
+}
@@ -0,0 +1,539 @@
+//! Bounding volumes, view frustum and rays
+//!
+//! Used for rejecting whole objects (or their parts)
+//! before their triangles are transformed and for
+//! finding objects under the cursor
+//!
+
+use crate::draw_3d::Vec3D;
+use crate::transform::Mat4x4;
+
+/// Axis-aligned bounding box
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3D,
+    pub max: Vec3D,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3D, max: Vec3D) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// Box containing nothing, grows to the first point added
+    ///
+    pub fn empty() -> Aabb {
+        Aabb::new(
+            Vec3D::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            Vec3D::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        )
+    }
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vec3D>>(points: I) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for p in points {
+            aabb.add_point(p);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn add_point(&mut self, p: &Vec3D) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(self.min.min(&other.min), self.max.max(&other.max))
+    }
+
+    pub fn center(&self) -> Vec3D {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3D {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let s = self.size();
+        2.0 * (s.x * s.y + s.y * s.z + s.z * s.x)
+    }
+
+    /// Corners of the box, bit 0 of the index selects max x,
+    /// bit 1 max y and bit 2 max z
+    ///
+    pub fn corners(&self) -> [Vec3D; 8] {
+        let mut corners = [self.min; 8];
+        for (i, c) in corners.iter_mut().enumerate() {
+            if i & 1 != 0 {
+                c.x = self.max.x;
+            }
+            if i & 2 != 0 {
+                c.y = self.max.y;
+            }
+            if i & 4 != 0 {
+                c.z = self.max.z;
+            }
+        }
+        corners
+    }
+}
+
+/// Bounding sphere
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Vec3D,
+    /// Negative for sphere containing nothing
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3D, radius: f32) -> Sphere {
+        Sphere { center, radius }
+    }
+
+    /// Sphere around center of `aabb` enclosing all `points`
+    ///
+    /// Not the smallest possible, but cheap and good enough for culling
+    ///
+    pub fn from_points<'a, I>(aabb: &Aabb, points: I) -> Sphere
+    where
+        I: IntoIterator<Item = &'a Vec3D>,
+    {
+        if aabb.is_empty() {
+            return Sphere::new(Vec3D::default(), -1.0);
+        }
+
+        let center = aabb.center();
+        let radius = points
+            .into_iter()
+            .map(|p| (*p - center).len())
+            .fold(0.0, f32::max);
+        Sphere::new(center, radius)
+    }
+}
+
+/// Plane `normal . p + d = 0`, points with positive
+/// distance are in front of the plane
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3D,
+    pub d: f32,
+}
+
+impl Plane {
+    /// Plane from `a * x + b * y + c * z + d = 0` with normal scaled
+    /// to unit length, so `distance` is in world units
+    ///
+    pub fn from_coefficients(a: f32, b: f32, c: f32, d: f32) -> Plane {
+        let normal = Vec3D::new(a, b, c);
+        let len = normal.len();
+        if len == 0.0 {
+            return Plane { normal, d };
+        }
+        Plane {
+            normal: normal / len,
+            d: d / len,
+        }
+    }
+
+    /// Signed distance of the point from the plane
+    ///
+    pub fn distance(&self, p: &Vec3D) -> f32 {
+        self.normal.dot_product(p) + self.d
+    }
+}
+
+/// Result of testing a volume against frustum
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Intersection {
+    Outside,
+    /// Crosses at least one plane
+    Partial,
+    Inside,
+}
+
+/// Volume visible by the camera, bounded by six planes facing inwards
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts planes from projection matrix (Gribb-Hartmann method)
+    ///
+    /// For `world * view * proj` the frustum is in object space, for
+    /// `view * proj` in world space. Expects depth range [0, 1]
+    ///
+    pub fn from_matrix(m: &Mat4x4) -> Frustum {
+        // Vectors are rows, so clip coordinates are dot
+        // products with columns of the matrix
+        let col = |c: usize| [m.m[0][c], m.m[1][c], m.m[2][c], m.m[3][c]];
+        let (x, y, z, w) = (col(0), col(1), col(2), col(3));
+        let plane = |k: f32, a: [f32; 4]| {
+            Plane::from_coefficients(
+                w[0] * k + a[0],
+                w[1] * k + a[1],
+                w[2] * k + a[2],
+                w[3] * k + a[3],
+            )
+        };
+        let neg = |a: [f32; 4]| [-a[0], -a[1], -a[2], -a[3]];
+
+        Frustum {
+            planes: [
+                plane(1.0, x),
+                plane(1.0, neg(x)),
+                plane(1.0, y),
+                plane(1.0, neg(y)),
+                plane(0.0, z),
+                plane(1.0, neg(z)),
+            ],
+        }
+    }
+
+    pub fn test_sphere(&self, sphere: &Sphere) -> Intersection {
+        if sphere.radius < 0.0 {
+            return Intersection::Outside;
+        }
+
+        let mut result = Intersection::Inside;
+        for plane in &self.planes {
+            let distance = plane.distance(&sphere.center);
+            if distance < -sphere.radius {
+                return Intersection::Outside;
+            }
+            if distance < sphere.radius {
+                result = Intersection::Partial;
+            }
+        }
+        result
+    }
+
+    /// Tests box corners nearest to and farthest from each plane
+    ///
+    /// Boxes near frustum corners may be reported as `Partial`
+    /// even if they are outside
+    ///
+    pub fn test_aabb(&self, aabb: &Aabb) -> Intersection {
+        if aabb.is_empty() {
+            return Intersection::Outside;
+        }
+
+        let mut result = Intersection::Inside;
+        for plane in &self.planes {
+            let n = plane.normal;
+            let pick = |positive: bool, min: f32, max: f32| if positive { max } else { min };
+            let farthest = Vec3D::new(
+                pick(n.x >= 0.0, aabb.min.x, aabb.max.x),
+                pick(n.y >= 0.0, aabb.min.y, aabb.max.y),
+                pick(n.z >= 0.0, aabb.min.z, aabb.max.z),
+            );
+            let nearest = Vec3D::new(
+                pick(n.x < 0.0, aabb.min.x, aabb.max.x),
+                pick(n.y < 0.0, aabb.min.y, aabb.max.y),
+                pick(n.z < 0.0, aabb.min.z, aabb.max.z),
+            );
+
+            if plane.distance(&farthest) < 0.0 {
+                return Intersection::Outside;
+            }
+            if plane.distance(&nearest) < 0.0 {
+                result = Intersection::Partial;
+            }
+        }
+        result
+    }
+}
+
+/// Half-line starting at `origin`
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3D,
+    /// Does not have to be normalized, distances
+    /// are then in multiples of its length
+    pub dir: Vec3D,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3D, dir: Vec3D) -> Ray {
+        Ray { origin, dir }
+    }
+
+    /// Point at distance `t`
+    ///
+    pub fn at(&self, t: f32) -> Vec3D {
+        self.origin + self.dir * t
+    }
+
+    /// Distances where the ray enters and leaves the box (slab method),
+    /// entry is 0 if the ray starts inside
+    ///
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<(f32, f32)> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+
+        for &(origin, dir, min, max) in &[
+            (self.origin.x, self.dir.x, aabb.min.x, aabb.max.x),
+            (self.origin.y, self.dir.y, aabb.min.y, aabb.max.y),
+            (self.origin.z, self.dir.z, aabb.min.z, aabb.max.z),
+        ] {
+            if dir == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((min - origin) / dir, (max - origin) / dir);
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+
+    /// Distance to the triangle (Moller-Trumbore) and barycentric
+    /// weights of its vertices at the hit point, both sides are hit
+    ///
+    pub fn intersect_triangle(&self, p: &[Vec3D; 3]) -> Option<(f32, Vec3D)> {
+        let edge1 = p[1] - p[0];
+        let edge2 = p[2] - p[0];
+        let h = self.dir.cross_product(&edge2);
+        let det = edge1.dot_product(&h);
+        if det.abs() <= f32::EPSILON * edge1.len() * edge2.len() * self.dir.len() {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = self.origin - p[0];
+        let u = s.dot_product(&h) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross_product(&edge1);
+        let v = self.dir.dot_product(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot_product(&q) * inv_det;
+        if t >= 0.0 {
+            Some((t, Vec3D::new(1.0 - u - v, u, v)))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Camera at origin looking along +z, sees |x| <= z
+    /// and |y| <= z between z = 1 and z = 100
+    ///
+    fn frustum() -> Frustum {
+        Frustum::from_matrix(&Mat4x4::mat_proj(90.0, 1.0, 100.0, 1.0))
+    }
+
+    #[test]
+    fn frustum_planes_face_inwards() {
+        let frustum = frustum();
+        // Points just outside of left, right, bottom, top, near and far plane
+        let outside = [
+            Vec3D::new(-11.0, 0.0, 10.0),
+            Vec3D::new(11.0, 0.0, 10.0),
+            Vec3D::new(0.0, -11.0, 10.0),
+            Vec3D::new(0.0, 11.0, 10.0),
+            Vec3D::new(0.0, 0.0, 0.9),
+            Vec3D::new(0.0, 0.0, 101.0),
+        ];
+        for (i, p) in outside.iter().enumerate() {
+            for (j, plane) in frustum.planes.iter().enumerate() {
+                assert_eq!(plane.distance(p) < 0.0, i == j, "point {} plane {}", i, j);
+            }
+        }
+        for p in &[
+            Vec3D::new(9.9, 9.9, 10.0),
+            Vec3D::new(-9.9, -9.9, 10.0),
+            Vec3D::new(0.0, 0.0, 1.1),
+            Vec3D::new(0.0, 0.0, 99.0),
+        ] {
+            assert_eq!(
+                frustum.test_sphere(&Sphere::new(*p, 0.0)),
+                Intersection::Inside
+            );
+        }
+    }
+
+    #[test]
+    fn frustum_spheres() {
+        let frustum = frustum();
+        let test = |x, y, z, r| frustum.test_sphere(&Sphere::new(Vec3D::new(x, y, z), r));
+
+        assert_eq!(test(0.0, 0.0, 10.0, 1.0), Intersection::Inside);
+        assert_eq!(test(3.0, -2.0, 50.0, 5.0), Intersection::Inside);
+
+        assert_eq!(test(0.0, 0.0, -5.0, 1.0), Intersection::Outside);
+        assert_eq!(test(50.0, 0.0, 10.0, 1.0), Intersection::Outside);
+        assert_eq!(test(0.0, -50.0, 10.0, 1.0), Intersection::Outside);
+        assert_eq!(test(0.0, 0.0, 200.0, 1.0), Intersection::Outside);
+        assert_eq!(test(0.0, 0.0, 10.0, -1.0), Intersection::Outside);
+
+        assert_eq!(test(10.0, 0.0, 10.0, 1.0), Intersection::Partial);
+        assert_eq!(test(0.0, 0.0, 1.0, 0.5), Intersection::Partial);
+        assert_eq!(test(0.0, 0.0, 100.0, 2.0), Intersection::Partial);
+        assert_eq!(test(0.0, 0.0, 0.0, 1000.0), Intersection::Partial);
+    }
+
+    #[test]
+    fn frustum_boxes() {
+        let frustum = frustum();
+        let test = |min: (f32, f32, f32), max: (f32, f32, f32)| {
+            frustum.test_aabb(&Aabb::new(
+                Vec3D::new(min.0, min.1, min.2),
+                Vec3D::new(max.0, max.1, max.2),
+            ))
+        };
+
+        assert_eq!(
+            test((-1.0, -1.0, 9.0), (1.0, 1.0, 11.0)),
+            Intersection::Inside
+        );
+
+        assert_eq!(
+            test((20.0, -1.0, 9.0), (22.0, 1.0, 11.0)),
+            Intersection::Outside
+        );
+        assert_eq!(
+            test((-1.0, -1.0, -3.0), (1.0, 1.0, -1.0)),
+            Intersection::Outside
+        );
+        assert_eq!(
+            test((-1.0, -1.0, 150.0), (1.0, 1.0, 160.0)),
+            Intersection::Outside
+        );
+        assert_eq!(frustum.test_aabb(&Aabb::empty()), Intersection::Outside);
+
+        assert_eq!(
+            test((8.0, -1.0, 9.0), (12.0, 1.0, 11.0)),
+            Intersection::Partial
+        );
+        assert_eq!(
+            test((-1.0, -1.0, 0.5), (1.0, 1.0, 2.0)),
+            Intersection::Partial
+        );
+        assert_eq!(
+            test((-500.0, -500.0, -1.0), (500.0, 500.0, 500.0)),
+            Intersection::Partial
+        );
+    }
+
+    #[test]
+    fn ray_box() {
+        let aabb = Aabb::new(Vec3D::new(-1.0, -1.0, -1.0), Vec3D::new(1.0, 2.0, 1.0));
+        let hit = |origin: Vec3D, dir: Vec3D| Ray::new(origin, dir).intersect_aabb(&aabb);
+
+        assert_eq!(
+            hit(Vec3D::new(-3.0, 0.0, 0.0), Vec3D::new(1.0, 0.0, 0.0)),
+            Some((2.0, 4.0))
+        );
+        // Distances are in multiples of direction length
+        assert_eq!(
+            hit(Vec3D::new(0.0, 5.0, 0.0), Vec3D::new(0.0, -2.0, 0.0)),
+            Some((1.5, 3.0))
+        );
+        // Starting inside
+        assert_eq!(
+            hit(Vec3D::new(0.0, 0.0, 0.0), Vec3D::new(0.0, 0.0, 1.0)),
+            Some((0.0, 1.0))
+        );
+        // Pointing away, missing, parallel to a slab outside of it
+        assert_eq!(
+            hit(Vec3D::new(-3.0, 0.0, 0.0), Vec3D::new(-1.0, 0.0, 0.0)),
+            None
+        );
+        assert_eq!(
+            hit(Vec3D::new(-3.0, 0.0, 0.0), Vec3D::new(1.0, 2.0, 0.0)),
+            None
+        );
+        assert_eq!(
+            hit(Vec3D::new(-3.0, 3.0, 0.0), Vec3D::new(1.0, 0.0, 0.0)),
+            None
+        );
+
+        let (t_enter, t_exit) =
+            hit(Vec3D::new(-2.0, -2.0, 0.5), Vec3D::new(1.0, 1.0, 0.0)).unwrap();
+        assert!((t_enter - 1.0).abs() < 1e-6 && (t_exit - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ray_triangle() {
+        let p = [
+            Vec3D::new(0.0, 0.0, 2.0),
+            Vec3D::new(2.0, 0.0, 2.0),
+            Vec3D::new(0.0, 2.0, 2.0),
+        ];
+        let hit = |origin: Vec3D, dir: Vec3D| Ray::new(origin, dir).intersect_triangle(&p);
+
+        let (t, bary) = hit(Vec3D::new(0.5, 1.0, 0.0), Vec3D::new(0.0, 0.0, 1.0)).unwrap();
+        assert!((t - 2.0).abs() < 1e-6);
+        assert!((bary - Vec3D::new(0.25, 0.25, 0.5)).len() < 1e-6);
+
+        // Back side is hit too, at the same point
+        let (t, back) = hit(Vec3D::new(0.5, 1.0, 6.0), Vec3D::new(0.0, 0.0, -2.0)).unwrap();
+        assert!((t - 2.0).abs() < 1e-6 && (back - bary).len() < 1e-6);
+
+        // Outside of the triangle, behind the origin and parallel to it
+        assert_eq!(
+            hit(Vec3D::new(1.5, 1.5, 0.0), Vec3D::new(0.0, 0.0, 1.0)),
+            None
+        );
+        assert_eq!(
+            hit(Vec3D::new(0.5, 0.5, 3.0), Vec3D::new(0.0, 0.0, 1.0)),
+            None
+        );
+        assert_eq!(
+            hit(Vec3D::new(0.5, 0.5, 2.0), Vec3D::new(1.0, 0.0, 0.0)),
+            None
+        );
+
+        // Vertex weights of a corner
+        let (_, corner) = hit(Vec3D::new(2.0, 0.0, 0.0), Vec3D::new(0.0, 0.0, 1.0)).unwrap();
+        assert!((corner - Vec3D::new(0.0, 1.0, 0.0)).len() < 1e-6);
+    }
+
+    #[test]
+    fn frustum_in_object_space() {
+        // Object is moved 10 units in front of the camera
+        let m = Mat4x4::mat_trans(0.0, 0.0, 10.0) * Mat4x4::mat_proj(90.0, 1.0, 100.0, 1.0);
+        let frustum = Frustum::from_matrix(&m);
+        let sphere = |x, z| Sphere::new(Vec3D::new(x, 0.0, z), 1.0);
+
+        assert_eq!(frustum.test_sphere(&sphere(0.0, 0.0)), Intersection::Inside);
+        assert_eq!(
+            frustum.test_sphere(&sphere(0.0, -10.0)),
+            Intersection::Outside
+        );
+        assert_eq!(
+            frustum.test_sphere(&sphere(15.0, 0.0)),
+            Intersection::Outside
+        );
+    }
+}
@@ -0,0 +1,456 @@
+//! Bounding volume hierarchy over triangles of a mesh
+//!
+//! Built with surface area heuristic over binned centroids,
+//! used for frustum culling of mesh parts and ray queries
+//!
+
+use crate::bounds::{Aabb, Frustum, Intersection, Ray};
+use crate::draw_3d::{Triangle3D, Vec3D};
+use crate::pick;
+use crate::transform::Mat4x4;
+use sdl2::pixels::Color;
+use sdl2::render::WindowCanvas;
+use std::cmp::Ordering;
+
+/// Nodes with at most this many triangles may become leaves
+///
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Number of centroid bins split positions are picked from
+///
+const BINS: usize = 12;
+
+/// Below this depth splits are by median, keeps the tree
+/// shallow for degenerate input
+///
+const MAX_SAH_DEPTH: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeKind {
+    /// Triangles `Bvh::indices[start..end]`
+    Leaf { start: usize, end: usize },
+    /// Indices of child nodes
+    Interior { left: usize, right: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Node {
+    pub aabb: Aabb,
+    pub kind: NodeKind,
+}
+
+/// Closest intersection of ray with a mesh
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// Distance along the ray
+    pub t: f32,
+    /// Index into `Mesh::tris`
+    pub tri: usize,
+    /// Weights of the triangle vertices at the hit point
+    pub bary: Vec3D,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    /// Root is the first node, empty for empty meshes
+    pub nodes: Vec<Node>,
+    /// Triangle indices, ordered so every leaf has a continuous range
+    pub indices: Vec<usize>,
+}
+
+/// Data used while building
+///
+struct Builder<'a> {
+    bounds: &'a [Aabb],
+    centroids: &'a [Vec3D],
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn build(tris: &[Triangle3D]) -> Bvh {
+        let bounds: Vec<Aabb> = tris.iter().map(|tri| Aabb::from_points(&tri.p)).collect();
+        let centroids: Vec<Vec3D> = bounds.iter().map(|b| b.center()).collect();
+        let mut builder = Builder {
+            bounds: &bounds,
+            centroids: &centroids,
+            nodes: Vec::new(),
+            indices: (0..tris.len()).collect(),
+        };
+
+        if !tris.is_empty() {
+            builder.build_node(0, tris.len(), 0);
+        }
+        Bvh {
+            nodes: builder.nodes,
+            indices: builder.indices,
+        }
+    }
+
+    /// Number of levels, 0 for empty tree
+    ///
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        self.visit(|_, level| depth = depth.max(level + 1));
+        depth
+    }
+
+    /// Calls `f` with every node and its depth (root is 0)
+    ///
+    pub fn visit<F: FnMut(&Node, usize)>(&self, mut f: F) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![(0, 0)];
+        while let Some((i, level)) = stack.pop() {
+            let node = &self.nodes[i];
+            f(node, level);
+            if let NodeKind::Interior { left, right } = node.kind {
+                stack.push((right, level + 1));
+                stack.push((left, level + 1));
+            }
+        }
+    }
+
+    /// Calls `f` with indices of triangles in nodes intersecting
+    /// the frustum, in no particular order
+    ///
+    pub fn query_frustum<F: FnMut(usize)>(&self, frustum: &Frustum, mut f: F) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        // Children of nodes inside the frustum are not tested
+        let mut stack = vec![(0, false)];
+        while let Some((i, inside)) = stack.pop() {
+            let node = &self.nodes[i];
+            let inside = inside
+                || match frustum.test_aabb(&node.aabb) {
+                    Intersection::Outside => continue,
+                    Intersection::Partial => false,
+                    Intersection::Inside => true,
+                };
+
+            match node.kind {
+                NodeKind::Leaf { start, end } => {
+                    for &tri in &self.indices[start..end] {
+                        f(tri);
+                    }
+                }
+                NodeKind::Interior { left, right } => {
+                    stack.push((right, inside));
+                    stack.push((left, inside));
+                }
+            }
+        }
+    }
+
+    /// Closest triangle hit by the ray, `tris` are the
+    /// triangles the tree was built from
+    ///
+    pub fn intersect_ray(&self, tris: &[Triangle3D], ray: &Ray) -> Option<Hit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut closest: Option<Hit> = None;
+        let mut stack = vec![0];
+
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            match ray.intersect_aabb(&node.aabb) {
+                Some((t_enter, _)) if closest.is_none_or(|hit| t_enter <= hit.t) => {}
+                _ => continue,
+            }
+
+            match node.kind {
+                NodeKind::Leaf { start, end } => {
+                    for &tri in &self.indices[start..end] {
+                        if let Some((t, bary)) = ray.intersect_triangle(&tris[tri].p) {
+                            if closest.is_none_or(|hit| t < hit.t) {
+                                closest = Some(Hit { t, tri, bary });
+                            }
+                        }
+                    }
+                }
+                NodeKind::Interior { left, right } => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+            }
+        }
+        closest
+    }
+
+    /// Draws node boxes as wireframes over the canvas
+    ///
+    /// Nodes at depth `level` and leaves above it are drawn,
+    /// `m` is object space -> clip space matrix. Edges are
+    /// clipped to the near plane and the window, see `pick::line`
+    ///
+    pub fn draw(&self, m: &Mat4x4, level: usize, color: Color, canvas: &mut WindowCanvas) {
+        self.visit(|node, depth| {
+            let is_leaf = matches!(node.kind, NodeKind::Leaf { .. });
+            if depth != level && !(is_leaf && depth < level) {
+                return;
+            }
+
+            let corners = node.aabb.corners();
+            // Corners differing in a single coordinate share an edge
+            for i in 0..8 {
+                for &bit in &[1, 2, 4] {
+                    if i & bit == 0 {
+                        pick::line(&corners[i], &corners[i | bit], m, color, canvas);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl<'a> Builder<'a> {
+    /// Builds node over `indices[start..end]`, returns its index
+    ///
+    fn build_node(&mut self, start: usize, end: usize, depth: usize) -> usize {
+        let aabb = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(&self.bounds[i]));
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            aabb,
+            kind: NodeKind::Leaf { start, end },
+        });
+
+        let count = end - start;
+        if count <= 1 {
+            return node;
+        }
+
+        let mid = match self.split(start, end, &aabb, depth) {
+            Some(mid) => mid,
+            None => return node,
+        };
+        let left = self.build_node(start, mid, depth + 1);
+        let right = self.build_node(mid, end, depth + 1);
+        self.nodes[node].kind = NodeKind::Interior { left, right };
+
+        node
+    }
+
+    /// Reorders `indices[start..end]` into two groups, returns
+    /// start of the second one. `None` if node should be a leaf
+    ///
+    fn split(&mut self, start: usize, end: usize, aabb: &Aabb, depth: usize) -> Option<usize> {
+        let count = end - start;
+        let centroid_bounds =
+            Aabb::from_points(self.indices[start..end].iter().map(|&i| &self.centroids[i]));
+        let size = centroid_bounds.size();
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        let coord = |v: &Vec3D| match axis {
+            0 => v.x,
+            1 => v.y,
+            _ => v.z,
+        };
+        let (min, extent) = (coord(&centroid_bounds.min), coord(&size));
+
+        // All centroids at one point, no split position separates them
+        if extent <= 0.0 {
+            return if count <= MAX_LEAF_SIZE {
+                None
+            } else {
+                Some(start + count / 2)
+            };
+        }
+        if depth >= MAX_SAH_DEPTH {
+            return Some(self.median_split(start, end, &coord));
+        }
+
+        let bin_of = |c: &Vec3D| (((coord(c) - min) / extent * BINS as f32) as usize).min(BINS - 1);
+        let mut bins = [(0usize, Aabb::empty()); BINS];
+        for &i in &self.indices[start..end] {
+            let bin = &mut bins[bin_of(&self.centroids[i])];
+            bin.0 += 1;
+            bin.1 = bin.1.union(&self.bounds[i]);
+        }
+
+        // Cost of splitting after each bin, areas of left sides
+        // are accumulated forward and right sides backward
+        let mut costs = [0.0f32; BINS - 1];
+        let (mut left_count, mut left_box) = (0, Aabb::empty());
+        for b in 0..BINS - 1 {
+            left_count += bins[b].0;
+            left_box = left_box.union(&bins[b].1);
+            costs[b] = left_count as f32 * left_box.surface_area();
+        }
+        let (mut right_count, mut right_box) = (0, Aabb::empty());
+        for b in (1..BINS).rev() {
+            right_count += bins[b].0;
+            right_box = right_box.union(&bins[b].1);
+            costs[b - 1] += right_count as f32 * right_box.surface_area();
+        }
+
+        let (best, best_cost) = costs
+            .iter()
+            .copied()
+            .enumerate()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+            .unwrap();
+        let leaf_cost = count as f32 * aabb.surface_area();
+        if count <= MAX_LEAF_SIZE && best_cost >= leaf_cost {
+            return None;
+        }
+
+        let mut mid = start;
+        for i in start..end {
+            if bin_of(&self.centroids[self.indices[i]]) <= best {
+                self.indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == start || mid == end {
+            return Some(self.median_split(start, end, &coord));
+        }
+        Some(mid)
+    }
+
+    fn median_split<F: Fn(&Vec3D) -> f32>(&mut self, start: usize, end: usize, coord: &F) -> usize {
+        let mid = (end - start) / 2;
+        let centroids = self.centroids;
+        self.indices[start..end].select_nth_unstable_by(mid, |&a, &b| {
+            coord(&centroids[a])
+                .partial_cmp(&coord(&centroids[b]))
+                .unwrap_or(Ordering::Equal)
+        });
+        start + mid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::grid_mesh;
+    use crate::transform::Mat4x4;
+
+    /// Small triangles scattered in [-1, 1] cube, from a fixed seed
+    ///
+    fn soup(count: usize) -> Vec<Triangle3D> {
+        let mut seed = 12345u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let mut random_vec =
+            move |scale: f32| Vec3D::new(random() * scale, random() * scale, random() * scale);
+        (0..count)
+            .map(|_| {
+                let center = random_vec(1.0);
+                Triangle3D::new(
+                    center + random_vec(0.2),
+                    center + random_vec(0.2),
+                    center + random_vec(0.2),
+                )
+            })
+            .collect()
+    }
+
+    fn meshes() -> Vec<Vec<Triangle3D>> {
+        vec![soup(500), grid_mesh(20).tris, soup(3)]
+    }
+
+    /// Closest hit found by testing every triangle
+    ///
+    fn linear_scan(tris: &[Triangle3D], ray: &Ray) -> Option<f32> {
+        tris.iter()
+            .filter_map(|tri| ray.intersect_triangle(&tri.p))
+            .map(|(t, _)| t)
+            .fold(None, |closest: Option<f32>, t| {
+                Some(closest.map_or(t, |c| c.min(t)))
+            })
+    }
+
+    #[test]
+    fn leaves_cover_every_triangle_once() {
+        for tris in meshes() {
+            let bvh = Bvh::build(&tris);
+            let mut seen = vec![0; tris.len()];
+            bvh.visit(|node, _| {
+                if let NodeKind::Leaf { start, end } = node.kind {
+                    for &i in &bvh.indices[start..end] {
+                        seen[i] += 1;
+                        for p in &tris[i].p {
+                            assert!(p.min(&node.aabb.min) == node.aabb.min);
+                            assert!(p.max(&node.aabb.max) == node.aabb.max);
+                        }
+                    }
+                }
+            });
+            assert!(seen.iter().all(|&count| count == 1));
+        }
+        assert_eq!(Bvh::build(&[]).depth(), 0);
+    }
+
+    #[test]
+    fn ray_hits_match_linear_scan() {
+        for tris in meshes() {
+            let bvh = Bvh::build(&tris);
+            let mut hits = 0;
+            for i in 0..400 {
+                let angle = i as f32 * 0.37;
+                let origin = Vec3D::new(3.0 * angle.cos(), (i % 7) as f32 - 3.0, 3.0 * angle.sin());
+                let target = Vec3D::new(
+                    (i % 5) as f32 * 0.25 - 0.5,
+                    (i % 3) as f32 * 0.3 - 0.3,
+                    (i % 11) as f32 * 0.1 - 0.5,
+                );
+                let ray = Ray::new(origin, target - origin);
+
+                let hit = bvh.intersect_ray(&tris, &ray);
+                assert_eq!(hit.map(|hit| hit.t), linear_scan(&tris, &ray), "ray {}", i);
+                if let Some(hit) = hit {
+                    let (t, bary) = ray.intersect_triangle(&tris[hit.tri].p).unwrap();
+                    assert_eq!((t, bary), (hit.t, hit.bary));
+                    hits += 1;
+                }
+            }
+            assert!(hits > 0);
+        }
+    }
+
+    #[test]
+    fn frustum_query_contains_visible_triangles() {
+        let proj = Mat4x4::mat_proj(60.0, 1.0, 50.0, 0.5);
+        let views = [
+            Mat4x4::mat_trans(0.0, 0.0, 3.0),
+            Mat4x4::mat_trans(1.5, 0.0, 2.0),
+            Mat4x4::mat_rot_y(&1.0) * Mat4x4::mat_trans(0.0, -0.8, 1.0),
+            Mat4x4::mat_trans(0.0, 0.0, -3.0),
+        ];
+        for tris in meshes() {
+            let bvh = Bvh::build(&tris);
+            let mut counts = Vec::new();
+            for view in &views {
+                let frustum = Frustum::from_matrix(&(*view * proj));
+                let mut found = vec![false; tris.len()];
+                bvh.query_frustum(&frustum, |i| {
+                    assert!(!found[i], "triangle {} reported twice", i);
+                    found[i] = true;
+                });
+
+                for (i, tri) in tris.iter().enumerate() {
+                    let visible = frustum.test_aabb(&Aabb::from_points(&tri.p));
+                    if visible != Intersection::Outside {
+                        assert!(found[i], "triangle {} missing", i);
+                    }
+                }
+                counts.push(found.iter().filter(|&&found| found).count());
+            }
+            // Last view looks away from the mesh
+            assert!(counts[0] > 0 && counts[3] == 0, "{:?}", counts);
+        }
+    }
+}
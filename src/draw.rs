@@ -3,13 +3,12 @@
 //!
 
 
+use crate::font;
 use sdl2::pixels::Color;
-use sdl2::rect::Point;
-use sdl2::render::WindowCanvas;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{BlendMode, WindowCanvas};
 use std::cmp;
 
-pub const PIXEL_SIZE: i32 = 1;
-
 /// Triangle shape
 ///
 #[derive(Debug, Clone)]
@@ -29,28 +28,81 @@ impl Triangle {
 /// My own API for putting pixel on a screen
 ///
 /// This is discourged by SDL2 rust binding author(s). I do it
-/// because:
-/// 1. This is fun/educational project
-/// 2. Pixel in memory can be presented by more than one physical/canvas pixel
+/// because this is fun/educational project. 3D scene is drawn
+/// through `framebuffer::Framebuffer` which can be scaled up
 ///
 pub fn pixel(point: Point, color: Color, canvas: &mut WindowCanvas) {
     canvas.set_draw_color(color);
-    if PIXEL_SIZE == 1 {
-        canvas.draw_point(point).unwrap();
-    } else {
-        for dx in 0..PIXEL_SIZE {
-            for dy in 0..PIXEL_SIZE {
-                canvas
-                    .draw_point(Point::new(
-                        point.x * PIXEL_SIZE + dx,
-                        point.y * PIXEL_SIZE + dy,
-                    ))
-                    .unwrap();
-            }
+    canvas.draw_point(point).unwrap();
+}
+
+/// How source color is combined with color already on the screen
+///
+/// `Alpha` and `Additive` scale the source by its alpha, `Multiply`
+/// fades from destination to destination * source as alpha grows
+///
+/// Shapes in this module draw every pixel once, so they blend
+/// correctly. Outlines joined from lines (`triangle`, `polygon`,
+/// `rect`, `rounded_rect`) draw their corners twice
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Blend {
+    /// Source overwrites destination
+    None,
+    Alpha,
+    Additive,
+    Multiply,
+}
+
+impl Blend {
+    /// Blends `src` over `dst`, alpha of `dst` is kept
+    ///
+    pub fn apply(self, src: Color, dst: Color) -> Color {
+        let a = src.a as u32;
+        let channel = |s: u8, d: u8| -> u8 {
+            let (s, d) = (s as u32, d as u32);
+            let c = match self {
+                Blend::None => s,
+                Blend::Alpha => (s * a + d * (255 - a)) / 255,
+                Blend::Additive => (d + s * a / 255).min(255),
+                Blend::Multiply => (d * (255 - a) + d * s / 255 * a) / 255,
+            };
+            c as u8
+        };
+
+        match self {
+            Blend::None => src,
+            _ => Color::RGBA(
+                channel(src.r, dst.r),
+                channel(src.g, dst.g),
+                channel(src.b, dst.b),
+                dst.a,
+            ),
+        }
+    }
+
+    fn to_sdl(self) -> BlendMode {
+        match self {
+            Blend::None => BlendMode::None,
+            Blend::Alpha => BlendMode::Blend,
+            Blend::Additive => BlendMode::Add,
+            Blend::Multiply => BlendMode::Mod,
         }
     }
 }
 
+/// Same as `pixel`, but combines color with the canvas using `blend`
+///
+/// SDL's `Mod` ignores source alpha, so `Multiply` is not faded
+/// by alpha here unlike in `Blend::apply`
+///
+pub fn pixel_blend(point: Point, color: Color, blend: Blend, canvas: &mut WindowCanvas) {
+    let prev_blend_mode = canvas.blend_mode();
+    canvas.set_blend_mode(blend.to_sdl());
+    pixel(point, color, canvas);
+    canvas.set_blend_mode(prev_blend_mode);
+}
+
 pub fn line(point_a: Point, point_b: Point, color: Color, canvas: &mut WindowCanvas) {
     canvas.set_draw_color(color);
 
@@ -207,3 +259,953 @@ pub fn filled_flat_top_triangle(t: Triangle, color: Color, canvas: &mut WindowCa
         scan_line_y -= 1;
     }
 }
+
+/// Exact integer line (Bresenham's algorithm)
+///
+pub fn line_bresenham(point_a: Point, point_b: Point, color: Color, canvas: &mut WindowCanvas) {
+    bresenham(point_a, point_b, |x, y| {
+        pixel(Point::new(x, y), color, canvas)
+    });
+}
+
+/// Calls `plot` with every pixel of the line, both ends included
+///
+/// The line is always walked from its leftmost (then topmost) end,
+/// so swapping the ends gives the same pixels
+///
+fn bresenham<F: FnMut(i32, i32)>(point_a: Point, point_b: Point, mut plot: F) {
+    let (point_a, point_b) = if (point_b.x, point_b.y) < (point_a.x, point_a.y) {
+        (point_b, point_a)
+    } else {
+        (point_a, point_b)
+    };
+    let dx = (point_b.x - point_a.x).abs();
+    let dy = -(point_b.y - point_a.y).abs();
+    let step_x = if point_a.x < point_b.x { 1 } else { -1 };
+    let step_y = if point_a.y < point_b.y { 1 } else { -1 };
+    let mut err = dx + dy;
+    let mut x = point_a.x;
+    let mut y = point_a.y;
+
+    loop {
+        plot(x, y);
+        if x == point_b.x && y == point_b.y {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += step_x;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += step_y;
+        }
+    }
+}
+
+/// Anti-aliased line (Xiaolin Wu's algorithm)
+///
+/// Each step covers two pixels across the line, their alpha
+/// is scaled by how much of the line passes through them
+///
+pub fn line_aa(point_a: Point, point_b: Point, color: Color, canvas: &mut WindowCanvas) {
+    let prev_blend_mode = canvas.blend_mode();
+    canvas.set_blend_mode(BlendMode::Blend);
+
+    wu(point_a, point_b, |x, y, coverage| {
+        let alpha = (color.a as f32 * coverage).round() as u8;
+        pixel(
+            Point::new(x, y),
+            Color::RGBA(color.r, color.g, color.b, alpha),
+            canvas,
+        );
+    });
+
+    canvas.set_blend_mode(prev_blend_mode);
+}
+
+/// Calls `plot` with pixels of anti-aliased line and their coverage,
+/// coverages of pixels across the line add up to 1
+///
+fn wu<F: FnMut(i32, i32, f32)>(point_a: Point, point_b: Point, mut plot: F) {
+    let steep = (point_b.y - point_a.y).abs() > (point_b.x - point_a.x).abs();
+    let (mut a, mut b) = if steep {
+        (
+            Point::new(point_a.y, point_a.x),
+            Point::new(point_b.y, point_b.x),
+        )
+    } else {
+        (point_a, point_b)
+    };
+    if a.x > b.x {
+        std::mem::swap(&mut a, &mut b);
+    }
+
+    let dx = (b.x - a.x) as f32;
+    let dy = (b.y - a.y) as f32;
+    let gradient = if dx == 0.0 { 1.0 } else { dy / dx };
+
+    // Coordinates are swapped back for steep lines
+    let mut aa_plot = |x: i32, y: i32, coverage: f32| {
+        if coverage > 0.0 {
+            if steep {
+                plot(y, x, coverage);
+            } else {
+                plot(x, y, coverage);
+            }
+        }
+    };
+    for x in a.x..=b.x {
+        let inter_y = a.y as f32 + gradient * (x - a.x) as f32;
+        let y = inter_y.floor();
+        let coverage = inter_y - y;
+
+        aa_plot(x, y as i32, 1.0 - coverage);
+        aa_plot(x, y as i32 + 1, coverage);
+    }
+}
+
+/// Point with sub-pixel precision used when building thick lines
+///
+type PointF = (f32, f32);
+
+/// Shape of the ends of thick lines
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineCap {
+    /// Line ends exactly at the end point
+    Butt,
+    /// Line is extended by half of its width
+    Square,
+    /// Half circle around the end point
+    Round,
+}
+
+/// Shape of corners between segments of thick polylines
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineJoin {
+    /// Outer edges are extended until they meet. Falls back to
+    /// `Bevel` for sharp corners (see `MITER_LIMIT`)
+    Miter,
+    /// Outer corners are connected with straight edge
+    Bevel,
+    /// Circle around the corner point
+    Round,
+}
+
+/// Maximal ratio of miter length to half of line width
+///
+pub const MITER_LIMIT: f32 = 4.0;
+
+/// Line of given width
+///
+pub fn thick_line(
+    point_a: Point,
+    point_b: Point,
+    width: f32,
+    cap: LineCap,
+    color: Color,
+    canvas: &mut WindowCanvas,
+) {
+    polyline(
+        &[point_a, point_b],
+        width,
+        LineJoin::Bevel,
+        cap,
+        color,
+        canvas,
+    );
+}
+
+/// Connected thick line segments
+///
+/// Segments, caps and joins are filled together as one shape,
+/// so every pixel is drawn once even where they overlap
+///
+pub fn polyline(
+    points: &[Point],
+    width: f32,
+    join: LineJoin,
+    cap: LineCap,
+    color: Color,
+    canvas: &mut WindowCanvas,
+) {
+    let contours = stroke_contours(points, width, join, cap);
+    fill_contours(&contours, FillRule::NonZero, |x1, x2, y| {
+        span(x1, x2, y, color, canvas)
+    });
+}
+
+/// Outline of thick polyline as convex pieces (segment quads, caps
+/// and joins), all wound the same way so their union is
+/// filled by `FillRule::NonZero`
+///
+fn stroke_contours(points: &[Point], width: f32, join: LineJoin, cap: LineCap) -> Vec<Vec<PointF>> {
+    let half = width * 0.5;
+    let ps: Vec<PointF> = points.iter().map(|p| (p.x as f32, p.y as f32)).collect();
+    // Directions of segments, zero length segments are dropped
+    let mut segments: Vec<(PointF, PointF, PointF)> = Vec::new();
+    for w in ps.windows(2) {
+        let (dx, dy) = (w[1].0 - w[0].0, w[1].1 - w[0].1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len > 0.0 {
+            segments.push((w[0], w[1], (dx / len, dy / len)));
+        }
+    }
+
+    let mut contours = Vec::new();
+    if segments.is_empty() {
+        if let (Some(p), LineCap::Round) = (ps.first(), cap) {
+            contours.push(disc_contour(*p, half));
+        }
+        return contours;
+    }
+
+    let last = segments.len() - 1;
+    for (i, &(a, b, d)) in segments.iter().enumerate() {
+        let mut a = a;
+        let mut b = b;
+        if cap == LineCap::Square && i == 0 {
+            a = (a.0 - d.0 * half, a.1 - d.1 * half);
+        }
+        if cap == LineCap::Square && i == last {
+            b = (b.0 + d.0 * half, b.1 + d.1 * half);
+        }
+        let n = (-d.1 * half, d.0 * half);
+
+        contours.push(vec![
+            (a.0 + n.0, a.1 + n.1),
+            (b.0 + n.0, b.1 + n.1),
+            (b.0 - n.0, b.1 - n.1),
+            (a.0 - n.0, a.1 - n.1),
+        ]);
+    }
+
+    if cap == LineCap::Round {
+        contours.push(disc_contour(segments[0].0, half));
+        contours.push(disc_contour(segments[last].1, half));
+    }
+
+    for pair in segments.windows(2) {
+        let (_, p, d1) = pair[0];
+        let d2 = pair[1].2;
+
+        match join {
+            LineJoin::Round => contours.push(disc_contour(p, half)),
+            LineJoin::Bevel | LineJoin::Miter => {
+                // Join is needed only on the outer side of the turn
+                let turn = d1.0 * d2.1 - d1.1 * d2.0;
+                if turn == 0.0 {
+                    continue;
+                }
+                let side = if turn > 0.0 { -half } else { half };
+                let o1 = (p.0 - d1.1 * side, p.1 + d1.0 * side);
+                let o2 = (p.0 - d2.1 * side, p.1 + d2.0 * side);
+
+                // Miter tip lies on the bisector of both offsets
+                let (bx, by) = (o1.0 + o2.0 - 2.0 * p.0, o1.1 + o2.1 - 2.0 * p.1);
+                let b_len = (bx * bx + by * by).sqrt();
+                let cos_half = (bx * (o1.0 - p.0) + by * (o1.1 - p.1)) / (b_len * half);
+                if join == LineJoin::Miter && b_len > 0.0 && 1.0 / cos_half <= MITER_LIMIT {
+                    let m_len = half / cos_half;
+                    let m = (p.0 + bx / b_len * m_len, p.1 + by / b_len * m_len);
+                    contours.push(vec![p, o1, m, o2]);
+                } else {
+                    contours.push(vec![p, o1, o2]);
+                }
+            }
+        }
+    }
+
+    for contour in &mut contours {
+        if signed_area(contour) < 0.0 {
+            contour.reverse();
+        }
+    }
+    contours
+}
+
+/// Circle approximated by polygon, with edges at most
+/// a quarter of pixel inside the circle
+///
+fn disc_contour(center: PointF, radius: f32) -> Vec<PointF> {
+    let step = (1.0 - 0.25 / radius).max(-1.0).acos();
+    let count = (2.0 * std::f32::consts::PI / step).ceil().max(8.0) as usize;
+
+    (0..count)
+        .map(|i| {
+            let angle = i as f32 * 2.0 * std::f32::consts::PI / count as f32;
+            (
+                center.0 + radius * angle.cos(),
+                center.1 + radius * angle.sin(),
+            )
+        })
+        .collect()
+}
+
+/// Twice the area of polygon, positive for clockwise
+/// polygons on screen (y axis points down)
+///
+fn signed_area(points: &[PointF]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+    area
+}
+
+/// Circle outline (midpoint circle algorithm)
+///
+pub fn circle(center: Point, radius: i32, color: Color, canvas: &mut WindowCanvas) {
+    midpoint_circle(radius, |dx, dy| {
+        for &(x, y) in &octants(dx, dy) {
+            pixel(Point::new(center.x + x, center.y + y), color, canvas);
+        }
+    });
+}
+
+/// Filled circle, spans between points of midpoint circle
+///
+pub fn filled_circle(center: Point, radius: i32, color: Color, canvas: &mut WindowCanvas) {
+    filled_rows(center, &circle_rows(radius), color, canvas);
+}
+
+/// Part of circle outline between two angles
+///
+/// Angles are in degrees, measured from positive x axis
+/// clockwise on screen (y axis points down)
+///
+pub fn arc(
+    center: Point,
+    radius: i32,
+    start_deg: f32,
+    end_deg: f32,
+    color: Color,
+    canvas: &mut WindowCanvas,
+) {
+    let start = start_deg.rem_euclid(360.0);
+    let end = end_deg.rem_euclid(360.0);
+    // Full circle when angles differ by multiple of 360
+    let full = (end_deg - start_deg).abs() >= 360.0;
+
+    midpoint_circle(radius, |dx, dy| {
+        for &(x, y) in &octants(dx, dy) {
+            let a = (y as f32).atan2(x as f32).to_degrees().rem_euclid(360.0);
+            let inside = if start <= end {
+                a >= start && a <= end
+            } else {
+                a >= start || a <= end
+            };
+            if full || inside {
+                pixel(Point::new(center.x + x, center.y + y), color, canvas);
+            }
+        }
+    });
+}
+
+/// Ellipse outline (midpoint ellipse algorithm)
+///
+pub fn ellipse(center: Point, rx: i32, ry: i32, color: Color, canvas: &mut WindowCanvas) {
+    midpoint_ellipse(rx, ry, |dx, dy| {
+        for &(x, y) in &unique(&[(dx, dy), (-dx, dy), (dx, -dy), (-dx, -dy)]) {
+            pixel(Point::new(center.x + x, center.y + y), color, canvas);
+        }
+    });
+}
+
+pub fn filled_ellipse(center: Point, rx: i32, ry: i32, color: Color, canvas: &mut WindowCanvas) {
+    let mut rows = Vec::new();
+    midpoint_ellipse(rx, ry, |dx, dy| widen_row(&mut rows, dy, dx));
+    filled_rows(center, &rows, color, canvas);
+}
+
+/// Rectangle outline
+///
+pub fn rect(r: Rect, color: Color, canvas: &mut WindowCanvas) {
+    polygon(
+        &[
+            r.top_left(),
+            Point::new(r.right() - 1, r.top()),
+            Point::new(r.right() - 1, r.bottom() - 1),
+            Point::new(r.left(), r.bottom() - 1),
+        ],
+        color,
+        canvas,
+    );
+}
+
+/// Filled rectangle, drawn by SDL in one call
+///
+pub fn filled_rect(r: Rect, color: Color, canvas: &mut WindowCanvas) {
+    canvas.set_draw_color(color);
+    canvas.fill_rect(r).unwrap();
+}
+
+/// Same as `filled_rect`, but combines color with the canvas using `blend`
+///
+pub fn filled_rect_blend(r: Rect, color: Color, blend: Blend, canvas: &mut WindowCanvas) {
+    let prev_blend_mode = canvas.blend_mode();
+    canvas.set_blend_mode(blend.to_sdl());
+    filled_rect(r, color, canvas);
+    canvas.set_blend_mode(prev_blend_mode);
+}
+
+/// Rectangle outline with corners rounded by `radius`
+///
+pub fn rounded_rect(r: Rect, radius: i32, color: Color, canvas: &mut WindowCanvas) {
+    let radius = clamp_corner_radius(r, radius);
+    let (left, top) = (r.left() + radius, r.top() + radius);
+    let (right, bottom) = (r.right() - 1 - radius, r.bottom() - 1 - radius);
+
+    line(
+        Point::new(left, r.top()),
+        Point::new(right, r.top()),
+        color,
+        canvas,
+    );
+    line(
+        Point::new(left, r.bottom() - 1),
+        Point::new(right, r.bottom() - 1),
+        color,
+        canvas,
+    );
+    line(
+        Point::new(r.left(), top),
+        Point::new(r.left(), bottom),
+        color,
+        canvas,
+    );
+    line(
+        Point::new(r.right() - 1, top),
+        Point::new(r.right() - 1, bottom),
+        color,
+        canvas,
+    );
+
+    arc(Point::new(right, bottom), radius, 0.0, 90.0, color, canvas);
+    arc(Point::new(left, bottom), radius, 90.0, 180.0, color, canvas);
+    arc(Point::new(left, top), radius, 180.0, 270.0, color, canvas);
+    arc(Point::new(right, top), radius, 270.0, 360.0, color, canvas);
+}
+
+pub fn filled_rounded_rect(r: Rect, radius: i32, color: Color, canvas: &mut WindowCanvas) {
+    let radius = clamp_corner_radius(r, radius);
+    let (left, top) = (r.left() + radius, r.top() + radius);
+    let (right, bottom) = (r.right() - 1 - radius, r.bottom() - 1 - radius);
+
+    for y in top..=bottom {
+        span(r.left(), r.right() - 1, y, color, canvas);
+    }
+    // Rows of corners next to the straight part, which covers row 0
+    for (dy, &half) in circle_rows(radius).iter().enumerate().skip(1) {
+        let dy = dy as i32;
+        span(left - half, right + half, top - dy, color, canvas);
+        span(left - half, right + half, bottom + dy, color, canvas);
+    }
+}
+
+/// Rule deciding which parts of self-intersecting polygon are inside
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FillRule {
+    /// Inside if ray from the point crosses odd number of edges
+    EvenOdd,
+    /// Inside if polygon winds around the point at least once
+    NonZero,
+}
+
+/// Closed polygon outline
+///
+pub fn polygon(points: &[Point], color: Color, canvas: &mut WindowCanvas) {
+    for i in 0..points.len() {
+        line(points[i], points[(i + 1) % points.len()], color, canvas);
+    }
+}
+
+/// Filled polygon, may be concave and self-intersecting
+///
+/// Pixel is filled when its center is inside according to `rule`
+///
+pub fn filled_polygon(points: &[Point], rule: FillRule, color: Color, canvas: &mut WindowCanvas) {
+    filled_contours(&[points], rule, color, canvas);
+}
+
+/// Filled shape made of several closed contours,
+/// e.g. outline and holes of a glyph
+///
+pub fn filled_contours(
+    contours: &[&[Point]],
+    rule: FillRule,
+    color: Color,
+    canvas: &mut WindowCanvas,
+) {
+    let contours: Vec<Vec<PointF>> = contours
+        .iter()
+        .map(|c| c.iter().map(|p| (p.x as f32, p.y as f32)).collect())
+        .collect();
+
+    fill_contours(&contours, rule, |x1, x2, y| span(x1, x2, y, color, canvas));
+}
+
+/// Calls `span` with runs of pixels (`x1` to `x2` inclusive, `y`)
+/// whose centers are inside contours according to `rule`
+///
+/// Every pixel is passed at most once
+///
+fn fill_contours<F: FnMut(i32, i32, i32)>(contours: &[Vec<PointF>], rule: FillRule, mut span: F) {
+    let all = contours.iter().flat_map(|c| c.iter());
+    let min_y = all.clone().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    let max_y = all.map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+    if min_y > max_y {
+        return;
+    }
+    // Edge crossings of current scanline: x and winding direction
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+
+    // Rows with pixel centers in [min_y, max_y)
+    for y in (min_y - 0.5).ceil() as i32..(max_y - 0.5).ceil() as i32 {
+        let sy = y as f32 + 0.5;
+        crossings.clear();
+
+        for points in contours {
+            for i in 0..points.len() {
+                let a = points[i];
+                let b = points[(i + 1) % points.len()];
+                let (ay, by) = (a.1, b.1);
+                if (ay <= sy && sy < by) || (by <= sy && sy < ay) {
+                    let x = a.0 + (sy - ay) * (b.0 - a.0) / (by - ay);
+                    crossings.push((x, if by > ay { 1 } else { -1 }));
+                }
+            }
+        }
+
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut winding = 0;
+        for pair in crossings.windows(2) {
+            winding += pair[0].1;
+            let inside = match rule {
+                FillRule::EvenOdd => winding % 2 != 0,
+                FillRule::NonZero => winding != 0,
+            };
+            if inside {
+                // Pixel centers in [left, right)
+                let x1 = (pair[0].0 - 0.5).ceil() as i32;
+                let x2 = (pair[1].0 - 0.5).ceil() as i32 - 1;
+                if x1 <= x2 {
+                    span(x1, x2, y);
+                }
+            }
+        }
+    }
+}
+
+/// Horizontal run of pixels from `x1` to `x2` inclusive
+///
+fn span(x1: i32, x2: i32, y: i32, color: Color, canvas: &mut WindowCanvas) {
+    for x in cmp::min(x1, x2)..=cmp::max(x1, x2) {
+        pixel(Point::new(x, y), color, canvas);
+    }
+}
+
+fn clamp_corner_radius(r: Rect, radius: i32) -> i32 {
+    cmp::max(
+        0,
+        cmp::min(radius, cmp::min(r.width(), r.height()) as i32 / 2),
+    )
+}
+
+/// All eight symmetric points of a circle octant point. Points
+/// on axes and diagonals are listed once, so they are not drawn twice
+///
+fn octants(x: i32, y: i32) -> Vec<(i32, i32)> {
+    unique(&[
+        (x, y),
+        (y, x),
+        (-y, x),
+        (-x, y),
+        (-x, -y),
+        (-y, -x),
+        (y, -x),
+        (x, -y),
+    ])
+}
+
+fn unique(points: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let mut result = Vec::with_capacity(points.len());
+    for p in points {
+        if !result.contains(p) {
+            result.push(*p);
+        }
+    }
+    result
+}
+
+/// Half widths of rows of filled circle, indexed by
+/// distance of the row from the center
+///
+fn circle_rows(radius: i32) -> Vec<i32> {
+    let mut rows = Vec::new();
+    midpoint_circle(radius, |x, y| {
+        widen_row(&mut rows, y, x);
+        widen_row(&mut rows, x, y);
+    });
+    rows
+}
+
+/// Makes row `dy` of filled shape at least `half` pixels wide on each side
+///
+fn widen_row(rows: &mut Vec<i32>, dy: i32, half: i32) {
+    let dy = dy as usize;
+    if rows.len() <= dy {
+        rows.resize(dy + 1, 0);
+    }
+    rows[dy] = rows[dy].max(half);
+}
+
+/// Draws shape symmetric about `center` from half widths of its rows,
+/// every row is drawn once
+///
+fn filled_rows(center: Point, rows: &[i32], color: Color, canvas: &mut WindowCanvas) {
+    for (dy, &half) in rows.iter().enumerate() {
+        let (dy, x1, x2) = (dy as i32, center.x - half, center.x + half);
+        span(x1, x2, center.y + dy, color, canvas);
+        if dy != 0 {
+            span(x1, x2, center.y - dy, color, canvas);
+        }
+    }
+}
+
+/// Walks one octant of a circle centered at origin,
+/// calling `plot` with points where `x >= y >= 0`
+///
+fn midpoint_circle<F: FnMut(i32, i32)>(radius: i32, mut plot: F) {
+    let mut x = radius;
+    let mut y = 0;
+    let mut err = 1 - radius;
+
+    while x >= y {
+        plot(x, y);
+        y += 1;
+        if err < 0 {
+            err += 2 * y + 1;
+        } else {
+            x -= 1;
+            err += 2 * (y - x) + 1;
+        }
+    }
+}
+
+/// Walks one quadrant of an ellipse centered at origin,
+/// calling `plot` with points where `x >= 0` and `y >= 0`
+///
+fn midpoint_ellipse<F: FnMut(i32, i32)>(rx: i32, ry: i32, mut plot: F) {
+    let (rx2, ry2) = (rx as i64 * rx as i64, ry as i64 * ry as i64);
+    let mut x: i64 = 0;
+    let mut y: i64 = ry as i64;
+    let mut dx = 0;
+    let mut dy = 2 * rx2 * y;
+
+    // Region 1, slope > -1
+    let mut p = 4 * ry2 - 4 * rx2 * ry as i64 + rx2;
+    while dx < dy {
+        plot(x as i32, y as i32);
+        x += 1;
+        dx += 2 * ry2;
+        if p < 0 {
+            p += 4 * (dx + ry2);
+        } else {
+            y -= 1;
+            dy -= 2 * rx2;
+            p += 4 * (dx - dy + ry2);
+        }
+    }
+
+    // Region 2, slope <= -1
+    let mut p = ry2 * (2 * x + 1) * (2 * x + 1) + 4 * rx2 * (y - 1) * (y - 1) - 4 * rx2 * ry2;
+    while y >= 0 {
+        plot(x as i32, y as i32);
+        y -= 1;
+        dy -= 2 * rx2;
+        if p > 0 {
+            p += 4 * (rx2 - dy);
+        } else {
+            x += 1;
+            dx += 2 * ry2;
+            p += 4 * (dx - dy + rx2);
+        }
+    }
+}
+
+/// Draws text using built-in bitmap font
+///
+/// `position` is top left corner of the first character, every font
+/// pixel is drawn as `scale` x `scale` square. `\n` starts a new line
+///
+pub fn text(s: &str, position: Point, color: Color, scale: i32, canvas: &mut WindowCanvas) {
+    let mut x = position.x;
+    let mut y = position.y;
+
+    for c in s.chars() {
+        if c == '\n' {
+            x = position.x;
+            y += (font::GLYPH_HEIGHT + 1) * scale;
+            continue;
+        }
+
+        for (col, bits) in font::glyph(c).iter().enumerate() {
+            for row in 0..font::GLYPH_HEIGHT {
+                if bits >> row & 1 != 0 {
+                    filled_rect(
+                        Rect::new(
+                            x + col as i32 * scale,
+                            y + row * scale,
+                            scale as u32,
+                            scale as u32,
+                        ),
+                        color,
+                        canvas,
+                    );
+                }
+            }
+        }
+        x += (font::GLYPH_WIDTH + 1) * scale;
+    }
+}
+
+/// Width and height of area covered by `text`
+///
+pub fn text_size(s: &str, scale: i32) -> (i32, i32) {
+    let lines = s.split('\n');
+    let columns = lines.clone().map(|l| l.chars().count()).max().unwrap_or(0) as i32;
+    let rows = lines.count() as i32;
+
+    (
+        (columns * (font::GLYPH_WIDTH + 1) - 1).max(0) * scale,
+        (rows * (font::GLYPH_HEIGHT + 1) - 1) * scale,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn bresenham_pixels(a: Point, b: Point) -> Vec<(i32, i32)> {
+        let mut pixels = Vec::new();
+        bresenham(a, b, |x, y| pixels.push((x, y)));
+        pixels
+    }
+
+    /// Number of times every pixel of thick polyline is drawn
+    ///
+    fn stroke_pixels(
+        points: &[Point],
+        width: f32,
+        join: LineJoin,
+        cap: LineCap,
+    ) -> HashMap<(i32, i32), u32> {
+        let mut pixels = HashMap::new();
+        let contours = stroke_contours(points, width, join, cap);
+        fill_contours(&contours, FillRule::NonZero, |x1, x2, y| {
+            for x in x1..=x2 {
+                *pixels.entry((x, y)).or_insert(0) += 1;
+            }
+        });
+        pixels
+    }
+
+    fn ends() -> Vec<(Point, Point)> {
+        let a = Point::new(3, -2);
+        [
+            (17, 4),
+            (7, 19),
+            (-9, 6),
+            (-4, -15),
+            (12, -2),
+            (3, 11),
+            (-6, -11),
+            (3, -2),
+            // Line passes exactly between two pixels
+            (7, 0),
+            (-3, 1),
+            (4, -4),
+        ]
+        .iter()
+        .map(|&(x, y)| (a, Point::new(x, y)))
+        .collect()
+    }
+
+    #[test]
+    fn blend_modes() {
+        let dst = Color::RGBA(100, 200, 40, 77);
+        let src = |a| Color::RGBA(200, 100, 255, a);
+
+        assert_eq!(Blend::None.apply(src(0), dst), src(0));
+        assert_eq!(Blend::None.apply(src(128), dst), src(128));
+        for &blend in &[Blend::Alpha, Blend::Additive, Blend::Multiply] {
+            assert_eq!(blend.apply(src(0), dst), dst, "{:?}", blend);
+        }
+
+        assert_eq!(
+            Blend::Alpha.apply(src(255), dst),
+            Color::RGBA(200, 100, 255, 77)
+        );
+        assert_eq!(
+            Blend::Alpha.apply(src(128), dst),
+            Color::RGBA(150, 149, 147, 77)
+        );
+
+        assert_eq!(
+            Blend::Additive.apply(src(255), dst),
+            Color::RGBA(255, 255, 255, 77)
+        );
+        assert_eq!(
+            Blend::Additive.apply(src(128), dst),
+            Color::RGBA(200, 250, 168, 77)
+        );
+
+        assert_eq!(
+            Blend::Multiply.apply(src(255), dst),
+            Color::RGBA(78, 78, 40, 77)
+        );
+        assert_eq!(
+            Blend::Multiply.apply(src(128), dst),
+            Color::RGBA(88, 138, 40, 77)
+        );
+    }
+
+    #[test]
+    fn circle_points_once() {
+        for radius in 0..20 {
+            let mut points = Vec::new();
+            midpoint_circle(radius, |x, y| points.extend(octants(x, y)));
+
+            let mut sorted = points.clone();
+            sorted.sort_unstable();
+            sorted.dedup();
+            assert_eq!(sorted.len(), points.len(), "radius {}", radius);
+            for &(x, y) in &points {
+                assert!(points.contains(&(-x, y)) && points.contains(&(y, x)));
+            }
+
+            // Rows of filled circle reach the outline and nothing
+            // spans it twice
+            let rows = circle_rows(radius);
+            assert_eq!(rows.len() as i32, radius + 1);
+            assert_eq!(rows[0], radius);
+            for (dy, &half) in rows.iter().enumerate() {
+                let widest = points
+                    .iter()
+                    .filter(|p| p.1 == dy as i32)
+                    .map(|p| p.0)
+                    .max();
+                assert_eq!(widest, Some(half), "radius {} row {}", radius, dy);
+            }
+        }
+    }
+
+    #[test]
+    fn bresenham_endpoints() {
+        for (a, b) in ends() {
+            let pixels = bresenham_pixels(a, b);
+            let steps = (b.x - a.x).abs().max((b.y - a.y).abs());
+            assert_eq!(pixels.len() as i32, steps + 1, "{:?} {:?}", a, b);
+            assert!(pixels.contains(&(a.x, a.y)) && pixels.contains(&(b.x, b.y)));
+            // Neighbouring pixels touch, at least by corners
+            for pair in pixels.windows(2) {
+                assert!((pair[0].0 - pair[1].0).abs() <= 1 && (pair[0].1 - pair[1].1).abs() <= 1);
+                assert_ne!(pair[0], pair[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn bresenham_symmetry() {
+        for (a, b) in ends() {
+            let mut forward = bresenham_pixels(a, b);
+            let mut backward = bresenham_pixels(b, a);
+            forward.sort_unstable();
+            backward.sort_unstable();
+            assert_eq!(forward, backward, "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn wu_coverage() {
+        for (a, b) in ends() {
+            let steep = (b.y - a.y).abs() > (b.x - a.x).abs();
+            let mut columns: HashMap<i32, f32> = HashMap::new();
+            let mut end_coverage = Vec::new();
+            wu(a, b, |x, y, coverage| {
+                *columns.entry(if steep { y } else { x }).or_insert(0.0) += coverage;
+                if (x, y) == (a.x, a.y) || (x, y) == (b.x, b.y) {
+                    end_coverage.push(coverage);
+                }
+            });
+
+            let steps = (b.x - a.x).abs().max((b.y - a.y).abs());
+            assert_eq!(columns.len() as i32, steps + 1);
+            for coverage in columns.values() {
+                assert!((coverage - 1.0).abs() < 1e-4, "{:?} {:?}", a, b);
+            }
+            assert!(end_coverage.iter().all(|&c| c > 0.999));
+        }
+    }
+
+    #[test]
+    fn thick_line_caps() {
+        let (a, b) = (Point::new(10, 10), Point::new(30, 10));
+        for &(cap, x1, x2) in &[(LineCap::Butt, 10, 29), (LineCap::Square, 8, 31)] {
+            let pixels = stroke_pixels(&[a, b], 4.0, LineJoin::Bevel, cap);
+            let mut expected = Vec::new();
+            for y in 8..12 {
+                for x in x1..=x2 {
+                    expected.push((x, y));
+                }
+            }
+            let mut covered: Vec<(i32, i32)> = pixels.keys().copied().collect();
+            covered.sort_unstable_by_key(|&(x, y)| (y, x));
+            assert_eq!(covered, expected, "{:?}", cap);
+        }
+
+        let round = stroke_pixels(&[a, b], 4.0, LineJoin::Bevel, LineCap::Round);
+        assert!(round.contains_key(&(8, 10)) && round.contains_key(&(31, 9)));
+        assert!(!round.contains_key(&(7, 10)) && !round.contains_key(&(8, 8)));
+        assert!(round.values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn joins_no_overdraw() {
+        let turns: [&[Point]; 3] = [
+            &[Point::new(10, 10), Point::new(40, 10), Point::new(40, 40)],
+            &[Point::new(10, 40), Point::new(25, 10), Point::new(40, 40)],
+            &[Point::new(10, 10), Point::new(40, 12), Point::new(10, 14)],
+        ];
+        for points in turns.iter() {
+            for &join in &[LineJoin::Miter, LineJoin::Bevel, LineJoin::Round] {
+                for &cap in &[LineCap::Butt, LineCap::Round] {
+                    let pixels = stroke_pixels(points, 6.0, join, cap);
+                    assert!(!pixels.is_empty());
+                    assert!(
+                        pixels.values().all(|&count| count == 1),
+                        "{:?} {:?} {:?}",
+                        points,
+                        join,
+                        cap
+                    );
+                }
+            }
+        }
+
+        // Outer corner of the right angle turn at (40, 10) is (43, 7)
+        let corner = |join| stroke_pixels(turns[0], 6.0, join, LineCap::Butt);
+        assert!(corner(LineJoin::Miter).contains_key(&(42, 7)));
+        assert!(!corner(LineJoin::Bevel).contains_key(&(42, 7)));
+        assert!(!corner(LineJoin::Round).contains_key(&(42, 7)));
+        assert!(corner(LineJoin::Round).contains_key(&(41, 8)));
+    }
+}
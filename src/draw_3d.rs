@@ -2,41 +2,31 @@
 //!
 //!
 
+use crate::bounds::{Aabb, Ray, Sphere};
+use crate::bvh::{Bvh, Hit};
 use crate::draw;
 use sdl2::pixels::Color;
 use sdl2::rect::Point;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
 
-/// Vertex vector
+/// 3D vector
 ///
-#[derive(Clone, Copy, Debug)]
+/// Used both for points and directions. For homogeneous
+/// coordinates (e.g. result of projection) see `Vec4D`
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Vec3D {
     pub x: f32,
     pub y: f32,
     pub z: f32,
-    pub w: f32,
-}
-
-impl Default for Vec3D {
-    fn default() -> Self {
-        Vec3D {
-            x: 0.0,
-            y: 0.0,
-            z: 0.0,
-            w: 1.0,
-        }
-    }
 }
 
 impl Vec3D {
     pub fn new(x: f32, y: f32, z: f32) -> Vec3D {
-        Vec3D {
-            x,
-            y,
-            z,
-            ..Default::default()
-        }
+        Vec3D { x, y, z }
     }
 
     pub fn dot_product(&self, other: &Vec3D) -> f32 {
@@ -47,34 +37,227 @@ impl Vec3D {
         self.dot_product(self).sqrt()
     }
 
+    /// Returns unit vector with the same direction
+    ///
+    /// Degenerate (zero length) vectors are returned unchanged,
+    /// use `try_normalize` to detect them
+    ///
     pub fn normalize(&self) -> Vec3D {
+        self.try_normalize().unwrap_or(*self)
+    }
+
+    /// Returns unit vector with the same direction or
+    /// `None` if vector is too short to have one
+    ///
+    pub fn try_normalize(&self) -> Option<Vec3D> {
         let l = self.len();
 
-        Vec3D::new(self.x / l, self.y / l, self.z / l)
+        if l <= f32::EPSILON {
+            return None;
+        }
+
+        Some(*self / l)
     }
 
-    pub fn sub(&self, other: &Vec3D) -> Vec3D {
-        Vec3D::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    pub fn cross_product(&self, other: &Vec3D) -> Vec3D {
+        let x = self.y * other.z - self.z * other.y;
+        let y = self.z * other.x - self.x * other.z;
+        let z = self.x * other.y - self.y * other.x;
+
+        Vec3D::new(x, y, z)
     }
 
-    pub fn add(&self, other: &Vec3D) -> Vec3D {
+    /// Linear interpolation, `t == 0.0` gives `self`, `t == 1.0` gives `other`
+    ///
+    pub fn lerp(&self, other: &Vec3D, t: f32) -> Vec3D {
+        *self + (*other - *self) * t
+    }
+
+    /// Reflects vector about plane given by unit `normal`
+    ///
+    pub fn reflect(&self, normal: &Vec3D) -> Vec3D {
+        *self - *normal * (2.0 * self.dot_product(normal))
+    }
+
+    /// Component-wise minimum
+    ///
+    pub fn min(&self, other: &Vec3D) -> Vec3D {
+        Vec3D::new(
+            self.x.min(other.x),
+            self.y.min(other.y),
+            self.z.min(other.z),
+        )
+    }
+
+    /// Component-wise maximum
+    ///
+    pub fn max(&self, other: &Vec3D) -> Vec3D {
+        Vec3D::new(
+            self.x.max(other.x),
+            self.y.max(other.y),
+            self.z.max(other.z),
+        )
+    }
+}
+
+impl Add for Vec3D {
+    type Output = Vec3D;
+
+    fn add(self, other: Vec3D) -> Vec3D {
         Vec3D::new(self.x + other.x, self.y + other.y, self.z + other.z)
     }
+}
 
-    pub fn mul(&self, other: &Vec3D) -> Vec3D {
+impl Sub for Vec3D {
+    type Output = Vec3D;
+
+    fn sub(self, other: Vec3D) -> Vec3D {
+        Vec3D::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+/// Component-wise multiplication
+///
+impl Mul for Vec3D {
+    type Output = Vec3D;
+
+    fn mul(self, other: Vec3D) -> Vec3D {
         Vec3D::new(self.x * other.x, self.y * other.y, self.z * other.z)
     }
+}
+
+impl Mul<f32> for Vec3D {
+    type Output = Vec3D;
+
+    fn mul(self, k: f32) -> Vec3D {
+        Vec3D::new(self.x * k, self.y * k, self.z * k)
+    }
+}
+
+impl Mul<Vec3D> for f32 {
+    type Output = Vec3D;
+
+    fn mul(self, v: Vec3D) -> Vec3D {
+        v * self
+    }
+}
 
-    pub fn div(&self, other: &Vec3D) -> Vec3D {
+/// Component-wise division
+///
+impl Div for Vec3D {
+    type Output = Vec3D;
+
+    fn div(self, other: Vec3D) -> Vec3D {
         Vec3D::new(self.x / other.x, self.y / other.y, self.z / other.z)
     }
+}
 
-    pub fn cross_product(&self, other: &Vec3D) -> Vec3D {
-        let x = self.y * other.z - self.z * other.y;
-        let y = self.z * other.x - self.x * other.z;
-        let z = self.x * other.y - self.y * other.x;
+impl Div<f32> for Vec3D {
+    type Output = Vec3D;
 
-        Vec3D::new(x, y, z)
+    fn div(self, k: f32) -> Vec3D {
+        Vec3D::new(self.x / k, self.y / k, self.z / k)
+    }
+}
+
+impl Neg for Vec3D {
+    type Output = Vec3D;
+
+    fn neg(self) -> Vec3D {
+        Vec3D::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl AddAssign for Vec3D {
+    fn add_assign(&mut self, other: Vec3D) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vec3D {
+    fn sub_assign(&mut self, other: Vec3D) {
+        *self = *self - other;
+    }
+}
+
+/// Point in homogeneous coordinates
+///
+/// Produced by projection matrices, `w` has to be
+/// divided out before the point can be drawn
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vec4D {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Vec4D {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Vec4D {
+        Vec4D { x, y, z, w }
+    }
+
+    /// Position, affected by translation
+    ///
+    pub fn from_point(p: &Vec3D) -> Vec4D {
+        Vec4D::new(p.x, p.y, p.z, 1.0)
+    }
+
+    /// Direction, not affected by translation
+    ///
+    pub fn from_direction(d: &Vec3D) -> Vec4D {
+        Vec4D::new(d.x, d.y, d.z, 0.0)
+    }
+
+    /// Drops `w` without dividing
+    ///
+    pub fn xyz(&self) -> Vec3D {
+        Vec3D::new(self.x, self.y, self.z)
+    }
+
+    /// Divides `x`, `y` and `z` by `w`
+    ///
+    pub fn perspective_divide(&self) -> Vec3D {
+        self.xyz() / self.w
+    }
+}
+
+/// Surface properties of a part of the mesh
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Material {
+    /// Diffuse color
+    pub color: Color,
+    /// 1.0 is fully opaque, 0.0 invisible
+    pub opacity: f32,
+    pub blend: draw::Blend,
+}
+
+impl Material {
+    pub fn new(color: Color, opacity: f32, blend: draw::Blend) -> Material {
+        Material {
+            color,
+            opacity,
+            blend,
+        }
+    }
+
+    /// Transparent surfaces depend on what is behind them, so they
+    /// have to be drawn after opaque ones, from back to front
+    ///
+    pub fn is_transparent(&self) -> bool {
+        match self.blend {
+            draw::Blend::None => false,
+            draw::Blend::Alpha => self.opacity < 1.0,
+            draw::Blend::Additive | draw::Blend::Multiply => true,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::new(Color::RGB(255, 255, 255), 1.0, draw::Blend::Alpha)
     }
 }
 
@@ -84,6 +267,8 @@ impl Vec3D {
 pub struct Triangle3D {
     pub p: [Vec3D; 3],
     pub color: Option<Color>,
+    /// Index into `Mesh::materials`
+    pub material: usize,
 }
 
 impl Triangle3D {
@@ -91,6 +276,7 @@ impl Triangle3D {
         Triangle3D {
             p: [a, b, c],
             color: None,
+            material: 0,
         }
     }
 
@@ -123,18 +309,79 @@ impl Triangle3D {
 ///
 pub struct Mesh {
     pub tris: Vec<Triangle3D>,
+    /// First material is used by triangles without one
+    pub materials: Vec<Material>,
+    /// Object space bounds, call `update_bounds`
+    /// after changing triangles
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+    /// Used by the pipeline for culling parts of the mesh,
+    /// `build_bvh` has to be called again after changing triangles
+    pub bvh: Option<Bvh>,
 }
 
 impl Mesh {
     pub fn new(tris: Vec<Triangle3D>) -> Mesh {
-        Mesh { tris }
+        let mut mesh = Mesh {
+            tris,
+            materials: vec![Material::default()],
+            aabb: Aabb::empty(),
+            sphere: Sphere::new(Vec3D::default(), -1.0),
+            bvh: None,
+        };
+        mesh.update_bounds();
+        mesh
+    }
+
+    pub fn update_bounds(&mut self) {
+        let tris = &self.tris;
+        let points = || tris.iter().flat_map(|tri| tri.p.iter());
+        self.aabb = Aabb::from_points(points());
+        self.sphere = Sphere::from_points(&self.aabb, points());
+    }
+
+    pub fn build_bvh(&mut self) {
+        self.bvh = Some(Bvh::build(&self.tris));
+    }
+
+    /// Closest triangle hit by object space ray, uses BVH
+    /// when built and tests every triangle otherwise
+    ///
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<Hit> {
+        if let Some(bvh) = &self.bvh {
+            return bvh.intersect_ray(&self.tris, ray);
+        }
+        let mut closest: Option<Hit> = None;
+        for (tri, triangle) in self.tris.iter().enumerate() {
+            if let Some((t, bary)) = ray.intersect_triangle(&triangle.p) {
+                if closest.is_none_or(|hit| t < hit.t) {
+                    closest = Some(Hit { t, tri, bary });
+                }
+            }
+        }
+        closest
+    }
+
+    /// Material of the triangle, default one if index is out of range
+    ///
+    pub fn material(&self, tri: &Triangle3D) -> Material {
+        self.materials
+            .get(tri.material)
+            .copied()
+            .unwrap_or_default()
     }
 
     /// Reads mesh object from OBJ file into memory
     ///
+    /// Materials are read from MTL files referenced by `mtllib`,
+    /// only diffuse color (`Kd`) and opacity (`d` or `Tr`) are used
+    ///
     pub fn from_obj(path: &str) -> Mesh {
         let file = File::open(path);
         let mut tris: Vec<Triangle3D> = Vec::new();
+        let mut materials = vec![Material::default()];
+        let mut material_names: HashMap<String, usize> = HashMap::new();
+        let mut material = 0;
         match file {
             Ok(content) => {
                 let reader = BufReader::new(content);
@@ -166,12 +413,19 @@ impl Mesh {
                                     f2 = fpart[0].parse().unwrap();
                                     let fpart: Vec<&str> = words[3].split("/").collect();
                                     f3 = fpart[0].parse().unwrap();
-                                    tris.push(Triangle3D::new(
+                                    let mut tri = Triangle3D::new(
                                         vs[f1 as usize - 1],
                                         vs[f2 as usize - 1],
                                         vs[f3 as usize - 1],
-                                    ));
+                                    );
+                                    tri.material = material;
+                                    tris.push(tri);
                                 }
+                            } else if words.len() > 1 && words[0] == "mtllib" {
+                                let mtl_path = std::path::Path::new(path).with_file_name(words[1]);
+                                read_mtl(&mtl_path, &mut materials, &mut material_names);
+                            } else if words.len() > 1 && words[0] == "usemtl" {
+                                material = material_names.get(words[1]).copied().unwrap_or(0);
                             }
                         }
                         Err(err) => {
@@ -180,9 +434,57 @@ impl Mesh {
                         }
                     }
                 }
-                Mesh::new(tris)
+                Mesh {
+                    materials,
+                    ..Mesh::new(tris)
+                }
             }
             Err(_) => Mesh::new(Vec::new()),
         }
     }
 }
+
+/// Appends materials of MTL file to `materials`, their
+/// indices are stored in `names`
+///
+fn read_mtl(
+    path: &std::path::Path,
+    materials: &mut Vec<Material>,
+    names: &mut HashMap<String, usize>,
+) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            println!("{:?}", err);
+            return;
+        }
+    };
+
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let current = materials.len() - 1;
+        let value = |i: usize| -> f32 { words.get(i).and_then(|w| w.parse().ok()).unwrap_or(1.0) };
+
+        match words.first() {
+            Some(&"newmtl") if words.len() > 1 => {
+                names.insert(words[1].to_string(), materials.len());
+                materials.push(Material::default());
+            }
+            // Ignore properties appearing before the first `newmtl`,
+            // they would otherwise change the default material
+            Some(_) if current == 0 => {}
+            Some(&"Kd") => {
+                let to_u8 = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+                materials[current].color =
+                    Color::RGB(to_u8(value(1)), to_u8(value(2)), to_u8(value(3)));
+            }
+            Some(&"d") => materials[current].opacity = value(1),
+            Some(&"Tr") => materials[current].opacity = 1.0 - value(1),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! Software framebuffer the pipeline renders into
+//!
+//! Holds color and depth for every sample, anti-aliased
+//! images are resolved into final pixels when presented
+//!
+//! Colors are stored packed as ARGB8888, so the resolved image is
+//! uploaded to a streaming texture in one go. Framebuffer can be
+//! smaller than the window, texture is then scaled up to the window
+//!
+//! Samples are stored tile by tile, so tiles can be handed out
+//! to threads as independent mutable slices
+//!
+
+use crate::draw::Blend;
+use crate::raster::{self, Bounds};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{BlendMode, Texture, TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
+
+/// Full-scene anti-aliasing method
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Antialiasing {
+    None,
+    /// Supersampling, scene is rendered at `n` times the resolution
+    /// in both directions and averaged down
+    Ssaa(i32),
+    /// Multisampling with `n` coverage and depth samples per pixel,
+    /// fragment shader still runs once per pixel
+    Msaa(usize),
+}
+
+impl Antialiasing {
+    /// Cycles through commonly used settings
+    ///
+    pub fn next(self) -> Antialiasing {
+        match self {
+            Antialiasing::None => Antialiasing::Ssaa(2),
+            Antialiasing::Ssaa(_) => Antialiasing::Msaa(4),
+            Antialiasing::Msaa(_) => Antialiasing::None,
+        }
+    }
+}
+
+/// Filtering used when presented image is scaled up
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Blocky pixels
+    Nearest,
+    Bilinear,
+}
+
+impl Filter {
+    pub fn toggle(self) -> Filter {
+        match self {
+            Filter::Nearest => Filter::Bilinear,
+            Filter::Bilinear => Filter::Nearest,
+        }
+    }
+
+    /// Value of SDL's render scale quality hint
+    ///
+    fn hint(self) -> &'static str {
+        match self {
+            Filter::Nearest => "0",
+            Filter::Bilinear => "1",
+        }
+    }
+}
+
+/// Width and height of a tile in rendered pixels
+///
+pub const TILE_SIZE: i32 = 32;
+
+/// Sample storage the pipeline renders into
+///
+/// Coordinates are in rendered pixels (see `Framebuffer::render_width`)
+///
+pub trait RenderTarget {
+    /// Samples per rendered pixel
+    ///
+    fn samples(&self) -> usize;
+
+    fn depth(&self, x: i32, y: i32, sample: usize) -> f32;
+
+    fn set_sample(&mut self, x: i32, y: i32, sample: usize, color: Color, depth: f32);
+
+    /// Writes depth only, color is left untouched
+    ///
+    fn set_depth(&mut self, x: i32, y: i32, sample: usize, depth: f32);
+
+    /// Combines color with the sample, depth is left untouched
+    ///
+    fn blend_sample(&mut self, x: i32, y: i32, sample: usize, color: Color, blend: Blend);
+}
+
+pub struct Framebuffer {
+    width: i32,
+    height: i32,
+    /// Supersampling factor, 1 if disabled
+    scale: i32,
+    /// Samples per rendered pixel, 1 if multisampling is disabled
+    samples: usize,
+    /// Number of tile columns
+    tiles_x: i32,
+    /// Packed by `pack`
+    color: Vec<u32>,
+    depth: Vec<f32>,
+}
+
+/// Part of the framebuffer which can be rendered independently
+///
+pub struct Tile<'a> {
+    /// Pixels of the framebuffer covered by the tile, in rendered pixels
+    pub bounds: Bounds,
+    samples: usize,
+    color: &'a mut [u32],
+    depth: &'a mut [f32],
+}
+
+impl Framebuffer {
+    /// Creates framebuffer presenting `width` x `height` pixels
+    ///
+    /// Panics for unsupported MSAA sample counts (see `raster::sample_pattern`)
+    ///
+    pub fn new(width: i32, height: i32, antialiasing: Antialiasing) -> Framebuffer {
+        let (scale, samples) = match antialiasing {
+            Antialiasing::None => (1, 1),
+            Antialiasing::Ssaa(n) => (n.max(1), 1),
+            Antialiasing::Msaa(n) => {
+                raster::sample_pattern(n);
+                (1, n)
+            }
+        };
+        // Tiles on right and bottom edges are allocated whole
+        let tiles_x = (width * scale + TILE_SIZE - 1) / TILE_SIZE;
+        let tiles_y = (height * scale + TILE_SIZE - 1) / TILE_SIZE;
+        let len = (tiles_x * tiles_y * TILE_SIZE * TILE_SIZE) as usize * samples;
+
+        Framebuffer {
+            width,
+            height,
+            scale,
+            samples,
+            tiles_x,
+            color: vec![pack(Color::RGB(0, 0, 0)); len],
+            depth: vec![f32::INFINITY; len],
+        }
+    }
+
+    /// Width triangles are rasterized at
+    ///
+    pub fn render_width(&self) -> i32 {
+        self.width * self.scale
+    }
+
+    /// Height triangles are rasterized at
+    ///
+    pub fn render_height(&self) -> i32 {
+        self.height * self.scale
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        let color = pack(color);
+        for c in self.color.iter_mut() {
+            *c = color;
+        }
+        for d in self.depth.iter_mut() {
+            *d = f32::INFINITY;
+        }
+    }
+
+    /// Index of the first sample of rendered pixel
+    ///
+    fn index(&self, x: i32, y: i32) -> usize {
+        let tile = (y / TILE_SIZE * self.tiles_x + x / TILE_SIZE) as usize;
+        let (tx, ty) = (x % TILE_SIZE, y % TILE_SIZE);
+
+        (tile * (TILE_SIZE * TILE_SIZE) as usize + (ty * TILE_SIZE + tx) as usize) * self.samples
+    }
+
+    /// Splits framebuffer into tiles, row by row
+    ///
+    pub fn tiles_mut(&mut self) -> Vec<Tile<'_>> {
+        let tile_len = (TILE_SIZE * TILE_SIZE) as usize * self.samples;
+        let (tiles_x, render_width, render_height) =
+            (self.tiles_x, self.render_width(), self.render_height());
+        let samples = self.samples;
+
+        self.color
+            .chunks_mut(tile_len)
+            .zip(self.depth.chunks_mut(tile_len))
+            .enumerate()
+            .map(|(i, (color, depth))| {
+                let x = i as i32 % tiles_x * TILE_SIZE;
+                let y = i as i32 / tiles_x * TILE_SIZE;
+                Tile {
+                    bounds: Bounds::new(
+                        x,
+                        y,
+                        (x + TILE_SIZE).min(render_width),
+                        (y + TILE_SIZE).min(render_height),
+                    ),
+                    samples,
+                    color,
+                    depth,
+                }
+            })
+            .collect()
+    }
+
+    /// Averages all samples belonging to presented pixel
+    ///
+    pub fn resolve(&self, x: i32, y: i32) -> Color {
+        unpack(self.resolve_packed(x, y))
+    }
+
+    fn resolve_packed(&self, x: i32, y: i32) -> u32 {
+        if self.scale == 1 && self.samples == 1 {
+            return self.color[self.index(x, y)];
+        }
+
+        let mut sum = [0u32; 4];
+        for sy in 0..self.scale {
+            for sx in 0..self.scale {
+                let i = self.index(x * self.scale + sx, y * self.scale + sy);
+                for &c in &self.color[i..i + self.samples] {
+                    for (channel, s) in sum.iter_mut().enumerate() {
+                        *s += c >> (channel * 8) & 0xff;
+                    }
+                }
+            }
+        }
+
+        let n = (self.scale * self.scale) as u32 * self.samples as u32;
+        sum.iter()
+            .enumerate()
+            .fold(0, |packed, (channel, s)| packed | (s / n) << (channel * 8))
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Creates texture `present` can upload resolved image into
+    ///
+    /// `filter` is used when the texture is scaled to the window,
+    /// SDL picks it up when texture is created. Texture replaces
+    /// canvas contents, alpha of the image is ignored
+    ///
+    pub fn create_texture<'a>(
+        &self,
+        creator: &'a TextureCreator<WindowContext>,
+        filter: Filter,
+    ) -> Texture<'a> {
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", filter.hint());
+        let mut texture = creator
+            .create_texture_streaming(
+                PixelFormatEnum::ARGB8888,
+                self.width as u32,
+                self.height as u32,
+            )
+            .unwrap();
+        // Formats with alpha are blended by default, previous
+        // frame's overlays would show through translucent pixels
+        texture.set_blend_mode(BlendMode::None);
+        texture
+    }
+
+    /// Raw samples, in tile order. Equal framebuffers
+    /// produce exactly the same image
+    ///
+    pub fn colors(&self) -> &[u32] {
+        &self.color
+    }
+
+    /// Resolves samples and draws the image scaled over the whole canvas
+    ///
+    /// `texture` has to be created by `create_texture` of this framebuffer
+    ///
+    pub fn present(&self, canvas: &mut WindowCanvas, texture: &mut Texture) {
+        let width = self.width as usize;
+        texture
+            .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                for y in 0..self.height {
+                    let row = &mut buffer[y as usize * pitch..][..width * 4];
+                    for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                        let color = self.resolve_packed(x as i32, y);
+                        pixel.copy_from_slice(&color.to_ne_bytes());
+                    }
+                }
+            })
+            .unwrap();
+        canvas.copy(texture, None, None).unwrap();
+    }
+}
+
+impl RenderTarget for Framebuffer {
+    fn samples(&self) -> usize {
+        self.samples
+    }
+
+    fn depth(&self, x: i32, y: i32, sample: usize) -> f32 {
+        self.depth[self.index(x, y) + sample]
+    }
+
+    fn set_sample(&mut self, x: i32, y: i32, sample: usize, color: Color, depth: f32) {
+        let i = self.index(x, y) + sample;
+        self.color[i] = pack(color);
+        self.depth[i] = depth;
+    }
+
+    fn set_depth(&mut self, x: i32, y: i32, sample: usize, depth: f32) {
+        let i = self.index(x, y) + sample;
+        self.depth[i] = depth;
+    }
+
+    fn blend_sample(&mut self, x: i32, y: i32, sample: usize, color: Color, blend: Blend) {
+        let i = self.index(x, y) + sample;
+        self.color[i] = pack(blend.apply(color, unpack(self.color[i])));
+    }
+}
+
+impl<'a> Tile<'a> {
+    fn index(&self, x: i32, y: i32) -> usize {
+        let (tx, ty) = (x - self.bounds.min_x, y - self.bounds.min_y);
+
+        (ty * TILE_SIZE + tx) as usize * self.samples
+    }
+}
+
+impl<'a> RenderTarget for Tile<'a> {
+    fn samples(&self) -> usize {
+        self.samples
+    }
+
+    fn depth(&self, x: i32, y: i32, sample: usize) -> f32 {
+        self.depth[self.index(x, y) + sample]
+    }
+
+    fn set_sample(&mut self, x: i32, y: i32, sample: usize, color: Color, depth: f32) {
+        let i = self.index(x, y) + sample;
+        self.color[i] = pack(color);
+        self.depth[i] = depth;
+    }
+
+    fn set_depth(&mut self, x: i32, y: i32, sample: usize, depth: f32) {
+        let i = self.index(x, y) + sample;
+        self.depth[i] = depth;
+    }
+
+    fn blend_sample(&mut self, x: i32, y: i32, sample: usize, color: Color, blend: Blend) {
+        let i = self.index(x, y) + sample;
+        self.color[i] = pack(blend.apply(color, unpack(self.color[i])));
+    }
+}
+
+/// Color -> ARGB8888 in native byte order, layout
+/// of `PixelFormatEnum::ARGB8888` textures
+///
+fn pack(color: Color) -> u32 {
+    (color.a as u32) << 24 | (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32
+}
+
+fn unpack(color: u32) -> Color {
+    Color::RGBA(
+        (color >> 16) as u8,
+        (color >> 8) as u8,
+        color as u8,
+        (color >> 24) as u8,
+    )
+}
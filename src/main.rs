@@ -9,37 +9,60 @@ extern crate sdl2;
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
-use std::cmp::Ordering;
+use sdl2::rect::{Point, Rect};
 use std::f32;
-use std::time::Duration;
-use std::time::SystemTime;
+use std::time::{Duration, Instant};
 
+pub mod bench;
+pub mod bounds;
+pub mod bvh;
 pub mod draw;
 pub mod draw_3d;
+pub mod font;
+pub mod framebuffer;
+pub mod path;
+pub mod pick;
+pub mod pipeline;
+pub mod profile;
+pub mod raster;
+pub mod shader;
+pub mod simd;
+pub mod timing;
 pub mod transform;
 
 fn main() {
     let color_black: Color = Color::RGB(0, 0, 0);
     let color_red: Color = Color::RGB(255, 0, 0);
+    let color_hud: Color = Color::RGB(0, 255, 0);
+    let color_hud_background: Color = Color::RGBA(0, 0, 0, 192);
+    let color_bvh: Color = Color::RGB(255, 160, 0);
+    let color_pick: Color = Color::RGB(255, 255, 0);
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
     let screen_width = 1000;
     let screen_height = 500;
+    // Scene is rendered in (screen size / pixel_scale) resolution
+    // and scaled up to the window
+    let mut pixel_scale = 1;
+    let mut filter = framebuffer::Filter::Nearest;
 
     let window = video_subsystem
-        .window(
-            "Rust Renderer",
-            (draw::PIXEL_SIZE * screen_width) as u32,
-            (draw::PIXEL_SIZE * screen_height) as u32,
-        )
+        .window("Rust Renderer", screen_width as u32, screen_height as u32)
         .position_centered()
         .build()
         .unwrap();
 
     let mut canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+
+    if std::env::args().any(|arg| arg == "--bench") {
+        bench::run(&mut canvas, &texture_creator);
+        return;
+    }
 
     canvas.set_draw_color(color_black);
     canvas.clear();
@@ -47,7 +70,9 @@ fn main() {
     canvas.present();
 
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let model = draw_3d::Mesh::from_obj("<PATH>");
+    let mut model = draw_3d::Mesh::from_obj("<PATH>");
+    model.build_bvh();
+    let bvh_depth = model.bvh.as_ref().map_or(0, |bvh| bvh.depth());
     let mut look_dir = draw_3d::Vec3D::new(0.0, 0.0, 0.0);
     let mut camera = draw_3d::Vec3D {
         ..Default::default()
@@ -57,28 +82,70 @@ fn main() {
         ..Default::default()
     })
     .normalize();
-    let mut yaw: f32 = 0.0;
+    let axis_x = draw_3d::Vec3D::new(1.0, 0.0, 0.0);
+    let axis_y = draw_3d::Vec3D::new(0.0, 1.0, 0.0);
+    let axis_z = draw_3d::Vec3D::new(0.0, 0.0, 1.0);
+    // Camera orientation eases towards target orientation
+    // set by keyboard input
+    let mut camera_rot = transform::Quat::id();
+    let mut prev_camera_rot = transform::Quat::id();
+    let mut camera_rot_target = transform::Quat::id();
     let near: f32 = 0.1;
     let far: f32 = 1000.0;
     let fov: f32 = 90.0;
     let aspect_ratio: f32 = screen_height as f32 / screen_width as f32;
-    let mat_proj = transform::Mat4x4::mat_proj(fov, aspect_ratio, far, near);
+    let mut projection = transform::Projection::Perspective;
 
-    let screen_width_half = screen_width as f32 * 0.5;
-    let screen_height_half = screen_height as f32 * 0.5;
+    let mut pipeline = pipeline::Pipeline::new();
+    let mut antialiasing = framebuffer::Antialiasing::None;
+    let mut framebuffer = framebuffer::Framebuffer::new(
+        screen_width / pixel_scale,
+        screen_height / pixel_scale,
+        antialiasing,
+    );
+    let mut texture = framebuffer.create_texture(&texture_creator, filter);
+    let mut show_normals = false;
+    let mut show_hud = false;
+    // Level of BVH drawn over the scene
+    let mut show_bvh: Option<usize> = None;
+    // Triangle selected by clicking, window position of
+    // a click waiting to be resolved after next frame's matrices
+    let mut picked: Option<pick::Pick> = None;
+    let mut pick_request: Option<(i32, i32)> = None;
     let mut theta = 0.0;
-    // HACK: pushing object further into space so my computer
-    // does not melt
-    let z_offset = 800.0;
+    // Object is placed just far enough for its bounding sphere to fit the view
+    let model_distance = (model.sphere.center.len() + model.sphere.radius.max(near))
+        / (fov * 0.5).to_radians().sin();
+
+    // Frame rate is capped at `--fps <n>` (60 by default),
+    // `--uncapped` renders as fast as possible
+    let args: Vec<String> = std::env::args().collect();
+    let target_fps = args
+        .iter()
+        .position(|arg| arg == "--fps")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|fps| fps.parse().ok())
+        .unwrap_or(60);
+    let uncapped = args.iter().any(|arg| arg == "--uncapped");
+    let mut pacer = timing::FramePacer::new(if uncapped { None } else { Some(target_fps) });
+    // Animation is updated 120 times per second whatever the frame rate
+    let mut fixed_step = timing::FixedTimestep::new(120);
+    // Stage times are averaged over last 60 frames, `--profile` prints
+    // them every second and `--profile-dump <file.csv|file.json>`
+    // writes all frames on exit
+    let profile_stdout = args.iter().any(|arg| arg == "--profile");
+    let profile_dump = args
+        .iter()
+        .position(|arg| arg == "--profile-dump")
+        .and_then(|i| args.get(i + 1));
+    let mut profiler = profile::Profiler::new(60, profile_dump.is_some());
+    let mut show_profile = false;
+    let mut last_report = Instant::now();
 
-    let mut prev_sys_time = SystemTime::now();
     'running: loop {
-        let sys_time = SystemTime::now();
-        let time_elapsed = sys_time.duration_since(prev_sys_time);
-        let time_elapsed_frac = match time_elapsed {
-            Ok(duration) => duration.as_millis() as f32 / 1000.0,
-            Err(_) => 0.0,
-        };
+        let frame_time = pacer.begin_frame();
+        let time_elapsed_frac = frame_time.as_secs_f32();
+        let mut clock = Instant::now();
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. }
@@ -120,17 +187,172 @@ fn main() {
                 } => {
                     camera.x -= 16.0 * time_elapsed_frac;
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    projection = projection.toggle();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::E),
+                    ..
+                } => {
+                    pipeline.rasterizer = pipeline.rasterizer.toggle();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    ..
+                } => {
+                    pipeline.threads = if pipeline.threads > 1 {
+                        1
+                    } else {
+                        pipeline::Pipeline::available_threads()
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => {
+                    antialiasing = antialiasing.next();
+                    framebuffer = framebuffer::Framebuffer::new(
+                        screen_width / pixel_scale,
+                        screen_height / pixel_scale,
+                        antialiasing,
+                    );
+                    texture = framebuffer.create_texture(&texture_creator, filter);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::LeftBracket),
+                    ..
+                } => {
+                    pixel_scale = (pixel_scale - 1).max(1);
+                    framebuffer = framebuffer::Framebuffer::new(
+                        screen_width / pixel_scale,
+                        screen_height / pixel_scale,
+                        antialiasing,
+                    );
+                    texture = framebuffer.create_texture(&texture_creator, filter);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::RightBracket),
+                    ..
+                } => {
+                    pixel_scale = (pixel_scale + 1).min(8);
+                    framebuffer = framebuffer::Framebuffer::new(
+                        screen_width / pixel_scale,
+                        screen_height / pixel_scale,
+                        antialiasing,
+                    );
+                    texture = framebuffer.create_texture(&texture_creator, filter);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::B),
+                    ..
+                } => {
+                    filter = filter.toggle();
+                    texture = framebuffer.create_texture(&texture_creator, filter);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::H),
+                    ..
+                } => {
+                    show_hud = !show_hud;
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    pick_request = Some((x, y));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::I),
+                    ..
+                } => {
+                    show_profile = !show_profile;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::U),
+                    ..
+                } => {
+                    pacer.target_fps = match pacer.target_fps {
+                        Some(_) => None,
+                        None => Some(target_fps),
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::G),
+                    ..
+                } => {
+                    pipeline.mode = pipeline.mode.next();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::C),
+                    ..
+                } => {
+                    pipeline.faces.cull_mode = pipeline.faces.cull_mode.next();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::O),
+                    ..
+                } => {
+                    pipeline.faces.front_face = pipeline.faces.front_face.toggle();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::L),
+                    ..
+                } => {
+                    pipeline.faces.two_sided_lighting = !pipeline.faces.two_sided_lighting;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::V),
+                    ..
+                } => {
+                    show_bvh = match show_bvh {
+                        None if bvh_depth > 0 => Some(0),
+                        Some(level) if level + 1 < bvh_depth => Some(level + 1),
+                        _ => None,
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } => {
+                    show_normals = !show_normals;
+                }
                 Event::KeyDown {
                     keycode: Some(Keycode::A),
                     ..
                 } => {
-                    yaw -= 2.0 * time_elapsed_frac;
+                    let turn =
+                        transform::Quat::from_axis_angle(&axis_y, &(2.0 * time_elapsed_frac));
+                    camera_rot_target = turn * camera_rot_target;
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::D),
                     ..
                 } => {
-                    yaw += 2.0 * time_elapsed_frac;
+                    let turn =
+                        transform::Quat::from_axis_angle(&axis_y, &(-2.0 * time_elapsed_frac));
+                    camera_rot_target = turn * camera_rot_target;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    ..
+                } => {
+                    // Pitch around camera's own x axis
+                    let turn =
+                        transform::Quat::from_axis_angle(&axis_x, &(-2.0 * time_elapsed_frac));
+                    camera_rot_target = camera_rot_target * turn;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F),
+                    ..
+                } => {
+                    let turn =
+                        transform::Quat::from_axis_angle(&axis_x, &(2.0 * time_elapsed_frac));
+                    camera_rot_target = camera_rot_target * turn;
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::W),
@@ -141,7 +363,7 @@ fn main() {
                         16.0 * time_elapsed_frac,
                         16.0 * time_elapsed_frac,
                     );
-                    camera = camera.add(&forward);
+                    camera += forward;
                 }
                 Event::KeyDown {
                     keycode: Some(Keycode::S),
@@ -152,108 +374,181 @@ fn main() {
                         16.0 * time_elapsed_frac,
                         16.0 * time_elapsed_frac,
                     );
-                    camera = camera.sub(&forward);
+                    camera -= forward;
                 }
                 _ => {}
             }
         }
+        clock = profiler.lap(profile::Stage::Events, clock);
 
-        //theta += 1.0 * time_elapsed_frac;
-        let mat_rot_x = transform::Mat4x4::mat_rot_x(&(theta * 0.5));
-        let mat_rot_z = transform::Mat4x4::mat_rot_z(&theta);
-        let mat_rot_y = transform::Mat4x4::mat_rot_y(&(theta * 0.3));
-        let mat_trans = transform::Mat4x4::mat_trans(0.0, 0.0, 0.0 + z_offset);
-        let mat_world = mat_rot_z.mul(&mat_rot_x).mul(&mat_trans).mul(&mat_rot_y);
-        let view_offset = draw_3d::Vec3D::new(1.0, 1.0, 0.0);
-        let screen_offset = draw_3d::Vec3D::new(screen_width_half, screen_height_half, 1.0);
+        for _ in 0..fixed_step.advance(frame_time) {
+            let dt = fixed_step.dt();
+            //theta += 1.0 * dt;
+            prev_camera_rot = camera_rot;
+            camera_rot = camera_rot.slerp(&camera_rot_target, (10.0 * dt).min(1.0));
+        }
+        // Rendered orientation lags up to one step behind,
+        // so it moves smoothly between updates
+        let render_rot = prev_camera_rot.slerp(&camera_rot, fixed_step.alpha());
 
-        let up = draw_3d::Vec3D::new(0.0, 1.0, 0.0);
-        let look_dir = draw_3d::Vec3D::new(0.0, 0.0, 1.0);
-        let camera_rot = transform::Mat4x4::mat_rot_y(&yaw);
-        let look_dir = transform::mult_matrix_vector(&look_dir, &camera_rot);
+        let model_rot = transform::Quat::from_axis_angle(&axis_y, &(-theta * 0.3))
+            * transform::Quat::from_axis_angle(&axis_x, &(theta * 0.5))
+            * transform::Quat::from_axis_angle(&axis_z, &theta);
+        let mat_trans = transform::Mat4x4::mat_trans(0.0, 0.0, model_distance);
+        let mat_world = model_rot.to_mat4x4().mul(&mat_trans);
 
+        let up = render_rot.rotate(&axis_y);
+        let look_dir = render_rot.rotate(&axis_z);
 
-        let target = camera.add(&look_dir);
+        let target = camera + look_dir;
         let mat_camera = transform::Mat4x4::point_at(&camera, &target, &up);
         let mat_view = mat_camera.to_look_at();
+        let mat_proj = match projection {
+            transform::Projection::Perspective => {
+                transform::Mat4x4::mat_proj(fov, aspect_ratio, far, near)
+            }
+            transform::Projection::Orthographic => {
+                // Size the view box so the object keeps roughly
+                // the same size as in perspective mode
+                let half_height = model_distance * (fov * 0.5).to_radians().tan();
+                let half_width = half_height / aspect_ratio;
+                transform::Mat4x4::mat_ortho(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    near,
+                    far,
+                )
+            }
+        };
 
-        prev_sys_time = sys_time;
+        let uniforms = shader::SceneUniforms::new(mat_world, mat_view, mat_proj, light);
+        if let Some((x, y)) = pick_request.take() {
+            let (width, height) = canvas.output_size().unwrap();
+            picked = pick::pick(&[(&model, &uniforms)], x, y, width, height);
+        }
+        clock = profiler.lap(profile::Stage::Update, clock);
 
-        canvas.set_draw_color(color_black);
-        canvas.clear();
+        framebuffer.clear(color_black);
+        profiler.lap(profile::Stage::Clear, clock);
+        let stats = if show_normals {
+            let normal_shader = shader::NormalShader;
+            pipeline.draw_mesh(
+                &model,
+                &uniforms,
+                &normal_shader,
+                &normal_shader,
+                &mut framebuffer,
+            )
+        } else {
+            let flat_shader = shader::FlatShader;
+            pipeline.draw_mesh(
+                &model,
+                &uniforms,
+                &flat_shader,
+                &flat_shader,
+                &mut framebuffer,
+            )
+        };
 
-        let mut tris_to_rater: Vec<draw_3d::Triangle3D> = Vec::new();
-        for i in 0..model.tris.len() {
-            //println!("triangle {}", i);
-            let mut tri_projected = draw_3d::Triangle3D::new_empty();
-            let mut tri_translated = draw_3d::Triangle3D::new_empty();
+        profiler.current.add(&stats.times);
 
-            // Rotation
-            for v in 0..3 {
-                tri_translated.p[v] =
-                    transform::mult_matrix_vector(&model.tris[i].p[v], &mat_world);
-            }
-            let line1 = tri_translated.p[1].sub(&tri_translated.p[0]);
-            let line2 = tri_translated.p[2].sub(&tri_translated.p[0]);
+        clock = Instant::now();
+        framebuffer.present(&mut canvas, &mut texture);
+        clock = profiler.lap(profile::Stage::Present, clock);
 
-            let normal = line1.cross_product(&line2).normalize();
-            if normal.dot_product(&tri_translated.p[0].sub(&camera)) < 0.0 {
-                for v in 0..3 {
-                    // Worlds space -> View space
-                    tri_translated.p[v] =
-                        transform::mult_matrix_vector(&tri_translated.p[v], &mat_view);
-                    // 3D -> 2D
-                    tri_projected.p[v] =
-                        transform::mult_matrix_vector(&tri_translated.p[v], &mat_proj);
-                    tri_projected.p[v] = tri_projected.p[v]
-                        .div(
-                            &(draw_3d::Vec3D {
-                                x: tri_projected.p[v].w,
-                                y: tri_projected.p[v].w,
-                                z: tri_projected.p[v].w,
-                                w: tri_projected.p[v].w,
-                            }),
-                        )
-                        .add(&view_offset)
-                        .mul(&screen_offset);
-                }
-                // Illumination
-                let light_dp = normal.dot_product(&light);
-                let mut shade = (255.0 * light_dp) as u8;
-                // Small hack for avoid pitch black shades
-                if shade == 0 {
-                    shade = 10;
-                }
-                let color = Color::RGB(shade, shade, shade);
-                tri_projected.color = Some(color);
-                tris_to_rater.push(tri_projected);
-            }
+        if let (Some(level), Some(bvh)) = (show_bvh, &model.bvh) {
+            bvh.draw(&uniforms.world_view_proj, level, color_bvh, &mut canvas);
+        }
+        if let Some(pick) = &picked {
+            pick::highlight(&model, pick, &uniforms, color_pick, &mut canvas);
         }
 
-        tris_to_rater.sort_by(|a, b| {
-            let z0 = (a.p[0].z + a.p[1].z + a.p[2].z) / 3.0;
-            let z1 = (b.p[0].z + b.p[1].z + b.p[2].z) / 3.0;
-            if z0 < z1 {
-                return Ordering::Greater;
-            } else if z0 == z1 {
-                return Ordering::Equal;
-            } else {
-                return Ordering::Less;
+        if show_hud {
+            let mut hud = format!(
+                "frame {:.1} ms {}\nres {}x{} {:?}\nthreads {}\nmode {:?}\ncull {:?} front {:?}{}\ntris {} culled {} drawn {}{}\ncam {:.1} {:.1} {:.1}\ndir {:.2} {:.2} {:.2}",
+                time_elapsed_frac * 1000.0,
+                match pacer.target_fps {
+                    Some(fps) => format!("cap {} fps", fps),
+                    None => "uncapped".to_string(),
+                },
+                framebuffer.width(),
+                framebuffer.height(),
+                filter,
+                pipeline.threads,
+                pipeline.mode,
+                pipeline.faces.cull_mode,
+                pipeline.faces.front_face,
+                if pipeline.faces.two_sided_lighting {
+                    " two-sided"
+                } else {
+                    ""
+                },
+                stats.total,
+                stats.culled,
+                stats.rasterized,
+                if stats.meshes_culled > 0 {
+                    " (off screen)"
+                } else {
+                    ""
+                },
+                camera.x,
+                camera.y,
+                camera.z,
+                look_dir.x,
+                look_dir.y,
+                look_dir.z,
+            );
+            if let Some(pick) = &picked {
+                hud.push_str(&format!(
+                    "\npick tri {} at {:.1} {:.1} {:.1}\nbary {:.2} {:.2} {:.2}",
+                    pick.tri,
+                    pick.point.x,
+                    pick.point.y,
+                    pick.point.z,
+                    pick.bary.x,
+                    pick.bary.y,
+                    pick.bary.z,
+                ));
             }
-        });
+            let (hud_width, hud_height) = draw::text_size(&hud, 2);
+            draw::filled_rect_blend(
+                Rect::new(0, 0, hud_width as u32 + 8, hud_height as u32 + 8),
+                color_hud_background,
+                draw::Blend::Alpha,
+                &mut canvas,
+            );
+            draw::text(&hud, Point::new(4, 4), color_hud, 2, &mut canvas);
+        }
+        if show_profile {
+            let report = profiler.report();
+            let (report_width, report_height) = draw::text_size(&report, 2);
+            let x = screen_width - report_width - 8;
+            draw::filled_rect_blend(
+                Rect::new(x, 0, report_width as u32 + 8, report_height as u32 + 8),
+                color_hud_background,
+                draw::Blend::Alpha,
+                &mut canvas,
+            );
+            draw::text(&report, Point::new(x + 4, 4), color_hud, 2, &mut canvas);
+        }
+        canvas.present();
+        clock = profiler.lap(profile::Stage::Overlay, clock);
 
-        for t in &mut tris_to_rater.into_iter() {
-            // Drawing
-            draw::filled_triangle(t.to_2d(), t.color.unwrap(), &mut canvas);
+        pacer.end_frame();
+        profiler.lap(profile::Stage::Idle, clock);
+        profiler.end_frame();
 
-            // Wireframe for debugging
-            //draw::triangle(t.to_2d(), color_red, &mut canvas);
+        if profile_stdout && last_report.elapsed() >= Duration::from_secs(1) {
+            println!("{}\n", profiler.report());
+            last_report = Instant::now();
         }
+    }
 
-        canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
-        //::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 120));
-        //::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 20));
-        //::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 5));
+    if let Some(path) = profile_dump {
+        if let Err(err) = profiler.dump(path) {
+            println!("{:?}", err);
+        }
     }
 }
@@ -0,0 +1,302 @@
+//! Bezier curves and vector paths for overlays and annotations
+//!
+//! Curves are flattened into line segments and drawn
+//! with line and polygon functions from `draw`
+//!
+
+use crate::draw;
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::render::WindowCanvas;
+
+/// Maximal distance (in pixels) of flattened segments from the curve
+///
+pub const FLATNESS: f32 = 0.25;
+
+/// Limit of curve subdivisions, guards against degenerate input
+///
+const MAX_DEPTH: u32 = 16;
+
+type PointF = (f32, f32);
+
+/// Single drawing command of a `Path`
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathCommand {
+    /// Starts new sub-path
+    MoveTo(Point),
+    LineTo(Point),
+    /// Quadratic Bezier curve with one control point
+    QuadTo(Point, Point),
+    /// Cubic Bezier curve with two control points
+    CubicTo(Point, Point, Point),
+    /// Connects end of sub-path with its start
+    Close,
+}
+
+/// Sequence of lines and curves which can be stroked or filled
+///
+#[derive(Clone, Debug, Default)]
+pub struct Path {
+    pub commands: Vec<PathCommand>,
+}
+
+/// Flattened sub-path
+///
+#[derive(Clone, Debug)]
+pub struct Polyline {
+    pub points: Vec<Point>,
+    pub closed: bool,
+}
+
+impl Path {
+    pub fn new() -> Path {
+        Path {
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn move_to(&mut self, p: Point) -> &mut Path {
+        self.commands.push(PathCommand::MoveTo(p));
+        self
+    }
+
+    pub fn line_to(&mut self, p: Point) -> &mut Path {
+        self.commands.push(PathCommand::LineTo(p));
+        self
+    }
+
+    pub fn quad_to(&mut self, control: Point, p: Point) -> &mut Path {
+        self.commands.push(PathCommand::QuadTo(control, p));
+        self
+    }
+
+    pub fn cubic_to(&mut self, control_1: Point, control_2: Point, p: Point) -> &mut Path {
+        self.commands
+            .push(PathCommand::CubicTo(control_1, control_2, p));
+        self
+    }
+
+    pub fn close(&mut self) -> &mut Path {
+        self.commands.push(PathCommand::Close);
+        self
+    }
+
+    /// Converts path into polylines, curves are subdivided until
+    /// they are within `tolerance` pixels from straight segments
+    ///
+    pub fn flatten(&self, tolerance: f32) -> Vec<Polyline> {
+        let mut result = Vec::new();
+        let mut current: Vec<PointF> = Vec::new();
+        let mut last: PointF = (0.0, 0.0);
+
+        for command in &self.commands {
+            match *command {
+                PathCommand::MoveTo(p) => {
+                    finish_polyline(&mut result, &mut current, false);
+                    last = to_f(p);
+                    current.push(last);
+                }
+                PathCommand::LineTo(p) => {
+                    if current.is_empty() {
+                        current.push(last);
+                    }
+                    last = to_f(p);
+                    current.push(last);
+                }
+                PathCommand::QuadTo(c, p) => {
+                    if current.is_empty() {
+                        current.push(last);
+                    }
+                    flatten_quad(last, to_f(c), to_f(p), tolerance, 0, &mut current);
+                    last = to_f(p);
+                }
+                PathCommand::CubicTo(c1, c2, p) => {
+                    if current.is_empty() {
+                        current.push(last);
+                    }
+                    flatten_cubic(
+                        last,
+                        to_f(c1),
+                        to_f(c2),
+                        to_f(p),
+                        tolerance,
+                        0,
+                        &mut current,
+                    );
+                    last = to_f(p);
+                }
+                PathCommand::Close => {
+                    if let Some(&start) = current.first() {
+                        last = start;
+                    }
+                    finish_polyline(&mut result, &mut current, true);
+                }
+            }
+        }
+        finish_polyline(&mut result, &mut current, false);
+
+        result
+    }
+
+    /// Draws one pixel wide outline of the path
+    ///
+    pub fn stroke(&self, color: Color, canvas: &mut WindowCanvas) {
+        for polyline in self.flatten(FLATNESS) {
+            let points = &polyline.points;
+            for pair in points.windows(2) {
+                draw::line(pair[0], pair[1], color, canvas);
+            }
+            if polyline.closed && points.len() > 2 {
+                draw::line(points[points.len() - 1], points[0], color, canvas);
+            }
+        }
+    }
+
+    /// Draws outline of the path with given width
+    ///
+    pub fn stroke_width(
+        &self,
+        width: f32,
+        join: draw::LineJoin,
+        cap: draw::LineCap,
+        color: Color,
+        canvas: &mut WindowCanvas,
+    ) {
+        for polyline in self.flatten(FLATNESS) {
+            let mut points = polyline.points;
+            if polyline.closed && points.len() > 2 {
+                // Walk past the start once more so it gets a join
+                points.push(points[0]);
+                points.push(points[1]);
+                draw::polyline(&points, width, join, draw::LineCap::Butt, color, canvas);
+            } else {
+                draw::polyline(&points, width, join, cap, color, canvas);
+            }
+        }
+    }
+
+    /// Fills area enclosed by the path, all sub-paths
+    /// are treated as closed
+    ///
+    pub fn fill(&self, rule: draw::FillRule, color: Color, canvas: &mut WindowCanvas) {
+        let polylines = self.flatten(FLATNESS);
+        let contours: Vec<&[Point]> = polylines.iter().map(|p| p.points.as_slice()).collect();
+
+        draw::filled_contours(&contours, rule, color, canvas);
+    }
+}
+
+/// Quadratic Bezier curve from `p0` to `p2`
+///
+pub fn quad_bezier(p0: Point, p1: Point, p2: Point, color: Color, canvas: &mut WindowCanvas) {
+    let mut path = Path::new();
+    path.move_to(p0).quad_to(p1, p2);
+    path.stroke(color, canvas);
+}
+
+/// Cubic Bezier curve from `p0` to `p3`
+///
+pub fn cubic_bezier(
+    p0: Point,
+    p1: Point,
+    p2: Point,
+    p3: Point,
+    color: Color,
+    canvas: &mut WindowCanvas,
+) {
+    let mut path = Path::new();
+    path.move_to(p0).cubic_to(p1, p2, p3);
+    path.stroke(color, canvas);
+}
+
+fn to_f(p: Point) -> PointF {
+    (p.x as f32, p.y as f32)
+}
+
+fn mid(a: PointF, b: PointF) -> PointF {
+    ((a.0 + b.0) * 0.5, (a.1 + b.1) * 0.5)
+}
+
+/// Distance of point `p` from line going through `a` and `b`
+///
+fn distance_to_line(p: PointF, a: PointF, b: PointF) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = (dx * dx + dy * dy).sqrt();
+
+    if len == 0.0 {
+        return ((p.0 - a.0).powi(2) + (p.1 - a.1).powi(2)).sqrt();
+    }
+    ((p.0 - a.0) * dy - (p.1 - a.1) * dx).abs() / len
+}
+
+/// Appends points of the curve (without its start) to `out`
+///
+fn flatten_quad(
+    p0: PointF,
+    p1: PointF,
+    p2: PointF,
+    tolerance: f32,
+    depth: u32,
+    out: &mut Vec<PointF>,
+) {
+    if depth >= MAX_DEPTH || distance_to_line(p1, p0, p2) <= tolerance {
+        out.push(p2);
+        return;
+    }
+
+    // de Casteljau subdivision at t = 0.5
+    let p01 = mid(p0, p1);
+    let p12 = mid(p1, p2);
+    let m = mid(p01, p12);
+
+    flatten_quad(p0, p01, m, tolerance, depth + 1, out);
+    flatten_quad(m, p12, p2, tolerance, depth + 1, out);
+}
+
+/// Appends points of the curve (without its start) to `out`
+///
+fn flatten_cubic(
+    p0: PointF,
+    p1: PointF,
+    p2: PointF,
+    p3: PointF,
+    tolerance: f32,
+    depth: u32,
+    out: &mut Vec<PointF>,
+) {
+    let flat = distance_to_line(p1, p0, p3).max(distance_to_line(p2, p0, p3)) <= tolerance;
+    if depth >= MAX_DEPTH || flat {
+        out.push(p3);
+        return;
+    }
+
+    // de Casteljau subdivision at t = 0.5
+    let p01 = mid(p0, p1);
+    let p12 = mid(p1, p2);
+    let p23 = mid(p2, p3);
+    let p012 = mid(p01, p12);
+    let p123 = mid(p12, p23);
+    let m = mid(p012, p123);
+
+    flatten_cubic(p0, p01, p012, m, tolerance, depth + 1, out);
+    flatten_cubic(m, p123, p23, p3, tolerance, depth + 1, out);
+}
+
+/// Rounds points to pixels, drops repeated ones and
+/// moves finished polyline into `result`
+///
+fn finish_polyline(result: &mut Vec<Polyline>, current: &mut Vec<PointF>, closed: bool) {
+    let mut points: Vec<Point> = Vec::with_capacity(current.len());
+
+    for p in current.drain(..) {
+        let p = Point::new(p.0.round() as i32, p.1.round() as i32);
+        if points.last() != Some(&p) {
+            points.push(p);
+        }
+    }
+
+    if points.len() > 1 {
+        result.push(Polyline { points, closed });
+    }
+}
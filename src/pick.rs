@@ -0,0 +1,356 @@
+//! Mouse picking of meshes and triangles
+//!
+//! Window position is unprojected through inverse of
+//! world-view-projection matrix into object space ray
+//! of every mesh, which is then tested against its triangles
+//!
+
+use crate::bounds::Ray;
+use crate::draw;
+use crate::draw_3d::{Mesh, Vec3D, Vec4D};
+use crate::raster::{self, Bounds};
+use crate::shader::SceneUniforms;
+use crate::transform::{self, Mat4x4};
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::render::WindowCanvas;
+
+/// Triangle under the cursor
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pick {
+    /// Index into meshes passed to `pick`
+    pub mesh: usize,
+    /// Index into `Mesh::tris`
+    pub tri: usize,
+    /// 0 on near plane, 1 on far plane
+    pub t: f32,
+    /// Weights of the triangle vertices at the hit point
+    pub bary: Vec3D,
+    /// Hit point in world space
+    pub point: Vec3D,
+}
+
+/// Object space ray through window position (x, y)
+///
+/// Starts on near plane and reaches far plane at distance 1,
+/// so distances are comparable between meshes. None if matrix
+/// can not be inverted
+///
+pub fn screen_ray(x: i32, y: i32, width: u32, height: u32, m: &Mat4x4) -> Option<Ray> {
+    let inverse = m.inverse()?;
+    // Center of the pixel in NDC
+    let ndc_x = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+    let ndc_y = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+    let unproject = |z: f32| {
+        let p = transform::mult_matrix_vec4(&Vec4D::new(ndc_x, ndc_y, z, 1.0), &inverse);
+        if p.w.abs() <= f32::EPSILON {
+            None
+        } else {
+            Some(p.perspective_divide())
+        }
+    };
+
+    let near = unproject(0.0)?;
+    let far = unproject(1.0)?;
+    Some(Ray::new(near, far - near))
+}
+
+/// Object space -> window position, None behind the camera
+/// or further than `raster::GUARD_BAND` pixels from the window
+///
+/// Points just in front of the camera plane project
+/// far away, their positions would not fit into `i32`
+///
+pub fn to_window(p: &Vec3D, m: &Mat4x4, width: u32, height: u32) -> Option<Point> {
+    let clip = transform::mult_matrix_vec4(&Vec4D::from_point(p), m);
+    if clip.w <= f32::EPSILON {
+        return None;
+    }
+    let ndc = clip.perspective_divide();
+    let (x, y) = (
+        (ndc.x + 1.0) * width as f32 * 0.5,
+        (ndc.y + 1.0) * height as f32 * 0.5,
+    );
+    // Also rejects NaN
+    if !(x.abs() <= raster::GUARD_BAND && y.abs() <= raster::GUARD_BAND) {
+        return None;
+    }
+    Some(Point::new(x as i32, y as i32))
+}
+
+/// Object space segment -> window space segment with depth
+///
+/// Segment is clipped to the near plane in clip space first, so
+/// ends behind or just in front of the camera do not end up at huge
+/// window positions. None if the whole segment is behind the near plane
+///
+pub fn clip_line(
+    a: &Vec3D,
+    b: &Vec3D,
+    m: &Mat4x4,
+    width: u32,
+    height: u32,
+) -> Option<(Vec3D, Vec3D)> {
+    let mut a = transform::mult_matrix_vec4(&Vec4D::from_point(a), m);
+    let mut b = transform::mult_matrix_vec4(&Vec4D::from_point(b), m);
+    // Near plane is z = 0 in clip space
+    let lerp = |a: &Vec4D, b: &Vec4D, t: f32| {
+        Vec4D::new(
+            a.x + (b.x - a.x) * t,
+            a.y + (b.y - a.y) * t,
+            a.z + (b.z - a.z) * t,
+            a.w + (b.w - a.w) * t,
+        )
+    };
+    if a.z < 0.0 && b.z < 0.0 {
+        return None;
+    }
+    if a.z < 0.0 {
+        a = lerp(&a, &b, a.z / (a.z - b.z));
+    } else if b.z < 0.0 {
+        b = lerp(&b, &a, b.z / (b.z - a.z));
+    }
+
+    let to_window = |clip: &Vec4D| {
+        if clip.w <= f32::EPSILON {
+            return None;
+        }
+        let ndc = clip.perspective_divide();
+        let p = Vec3D::new(
+            (ndc.x + 1.0) * width as f32 * 0.5,
+            (ndc.y + 1.0) * height as f32 * 0.5,
+            ndc.z,
+        );
+        if p.x.is_finite() && p.y.is_finite() {
+            Some(p)
+        } else {
+            None
+        }
+    };
+    Some((to_window(&a)?, to_window(&b)?))
+}
+
+/// Draws object space line segment over the canvas, clipped
+/// to the near plane and to the window
+///
+pub fn line(a: &Vec3D, b: &Vec3D, m: &Mat4x4, color: Color, canvas: &mut WindowCanvas) {
+    let (width, height) = canvas.output_size().unwrap();
+    if let Some((a, b)) = clip_line(a, b, m, width, height) {
+        let bounds = Bounds::screen(width as i32, height as i32);
+        raster::line(a, b, bounds, |x, y, _| {
+            draw::pixel(Point::new(x, y), color, canvas)
+        });
+    }
+}
+
+/// Closest triangle under window position (x, y) among meshes
+/// drawn with given uniforms
+///
+pub fn pick(
+    meshes: &[(&Mesh, &SceneUniforms)],
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) -> Option<Pick> {
+    let mut closest: Option<Pick> = None;
+    for (i, (mesh, uniforms)) in meshes.iter().enumerate() {
+        let ray = match screen_ray(x, y, width, height, &uniforms.world_view_proj) {
+            Some(ray) => ray,
+            None => continue,
+        };
+        // Skip meshes whose bounds are missed or are behind the closest hit
+        match ray.intersect_aabb(&mesh.aabb) {
+            Some((t_enter, _)) if closest.is_none_or(|pick| t_enter <= pick.t) => {}
+            _ => continue,
+        }
+        if let Some(hit) = mesh.intersect_ray(&ray) {
+            if closest.is_none_or(|pick| hit.t < pick.t) {
+                closest = Some(Pick {
+                    mesh: i,
+                    tri: hit.tri,
+                    t: hit.t,
+                    bary: hit.bary,
+                    point: transform::mult_matrix_vector(&ray.at(hit.t), &uniforms.world),
+                });
+            }
+        }
+    }
+    closest
+}
+
+/// Outlines picked triangle and marks the hit point
+///
+/// Edges are clipped like in `line`, so triangles crossing
+/// the near plane are outlined too
+///
+pub fn highlight(
+    mesh: &Mesh,
+    pick: &Pick,
+    uniforms: &SceneUniforms,
+    color: Color,
+    canvas: &mut WindowCanvas,
+) {
+    let (width, height) = canvas.output_size().unwrap();
+    let m = &uniforms.world_view_proj;
+    let p = &mesh.tris[pick.tri].p;
+    for i in 0..3 {
+        line(&p[i], &p[(i + 1) % 3], m, color, canvas);
+    }
+
+    let hit = p[0] * pick.bary.x + p[1] * pick.bary.y + p[2] * pick.bary.z;
+    if let Some(center) = to_window(&hit, m, width, height) {
+        draw::filled_circle(center, 3, color, canvas);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_3d::Triangle3D;
+
+    const WIDTH: u32 = 400;
+    const HEIGHT: u32 = 200;
+
+    fn uniforms(world: Mat4x4) -> SceneUniforms {
+        SceneUniforms::new(
+            world,
+            Mat4x4::id(),
+            Mat4x4::mat_proj(90.0, 0.5, 100.0, 0.1),
+            Vec3D::new(0.0, 0.0, 1.0),
+        )
+    }
+
+    /// Window position (not rounded) of object space point
+    ///
+    fn project(p: &Vec3D, m: &Mat4x4) -> (f32, f32) {
+        let ndc = transform::mult_matrix_vec4(&Vec4D::from_point(p), m).perspective_divide();
+        (
+            (ndc.x + 1.0) * WIDTH as f32 * 0.5,
+            (ndc.y + 1.0) * HEIGHT as f32 * 0.5,
+        )
+    }
+
+    #[test]
+    fn screen_ray_round_trip() {
+        let m =
+            uniforms(Mat4x4::mat_rot_y(&0.4) * Mat4x4::mat_trans(0.5, 0.0, 3.0)).world_view_proj;
+        for &(x, y) in &[(0, 0), (200, 100), (17, 150), (399, 199)] {
+            let ray = screen_ray(x, y, WIDTH, HEIGHT, &m).unwrap();
+            for &t in &[0.0, 0.01, 0.5, 1.0] {
+                let p = ray.at(t);
+                let (px, py) = project(&p, &m);
+                assert!((px - (x as f32 + 0.5)).abs() < 1e-2, "{} {} at {}", x, y, t);
+                assert!((py - (y as f32 + 0.5)).abs() < 1e-2, "{} {} at {}", x, y, t);
+            }
+            // Ray spans from near to far plane
+            let depth = |t: f32| {
+                transform::mult_matrix_vec4(&Vec4D::from_point(&ray.at(t)), &m)
+                    .perspective_divide()
+                    .z
+            };
+            assert!(depth(0.0).abs() < 1e-3 && (depth(1.0) - 1.0).abs() < 1e-3);
+        }
+        assert!(screen_ray(0, 0, WIDTH, HEIGHT, &Mat4x4::new_empty()).is_none());
+    }
+
+    #[test]
+    fn pick_nearest_triangle() {
+        let front = Triangle3D::new(
+            Vec3D::new(-1.0, -1.0, 0.0),
+            Vec3D::new(1.0, -0.5, 0.0),
+            Vec3D::new(0.0, 1.0, 0.2),
+        );
+        let mut back = front;
+        for p in &mut back.p {
+            p.z += 1.0;
+        }
+        let mesh = Mesh::new(vec![back, front]);
+        let mut bvh_mesh = Mesh::new(vec![back, front]);
+        bvh_mesh.build_bvh();
+        let near = uniforms(Mat4x4::mat_trans(0.0, 0.0, 2.0));
+        let far = uniforms(Mat4x4::mat_trans(0.0, 0.0, 2.5));
+        let m = &near.world_view_proj;
+
+        let bary = Vec3D::new(0.2, 0.3, 0.5);
+        let p = front.p[0] * bary.x + front.p[1] * bary.y + front.p[2] * bary.z;
+        let (x, y) = project(&p, m);
+        let (x, y) = (x as i32, y as i32);
+
+        for mesh in &[&mesh, &bvh_mesh] {
+            let pick = pick(&[(mesh, &far), (mesh, &near)], x, y, WIDTH, HEIGHT).unwrap();
+            assert_eq!((pick.mesh, pick.tri), (1, 1));
+            // Pixel center is at most half a pixel away
+            assert!((pick.bary - bary).len() < 0.02, "{:?}", pick.bary);
+            assert!((pick.bary.x + pick.bary.y + pick.bary.z - 1.0).abs() < 1e-5);
+            let world = transform::mult_matrix_vector(&p, &near.world);
+            assert!((pick.point - world).len() < 0.02, "{:?}", pick.point);
+        }
+
+        assert_eq!(pick(&[(&mesh, &near)], 0, 0, WIDTH, HEIGHT), None);
+    }
+
+    #[test]
+    fn to_window_rejects_points_near_camera_plane() {
+        let m = uniforms(Mat4x4::id()).world_view_proj;
+        assert_eq!(
+            to_window(&Vec3D::new(0.0, 0.0, 1.0), &m, WIDTH, HEIGHT),
+            Some(Point::new(200, 100))
+        );
+        assert_eq!(
+            to_window(&Vec3D::new(1.0, 1.0, -1.0), &m, WIDTH, HEIGHT),
+            None
+        );
+        assert_eq!(
+            to_window(&Vec3D::new(1.0, 1.0, 1e-6), &m, WIDTH, HEIGHT),
+            None
+        );
+        assert_eq!(
+            to_window(&Vec3D::new(0.0, 0.0, 1e-6), &m, WIDTH, HEIGHT),
+            Some(Point::new(200, 100))
+        );
+    }
+
+    #[test]
+    fn line_clipped_to_near_plane() {
+        let m = Mat4x4::mat_proj(90.0, 0.5, 100.0, 0.1);
+        let (width, height) = (200, 100);
+        let near_depth = |p: Vec3D| p.z.abs() < 1e-5;
+
+        // From behind the camera, and from just behind the near plane
+        for &behind in &[-5.0, 0.0, 0.09] {
+            let (a, b) = clip_line(
+                &Vec3D::new(1.0, 0.5, behind),
+                &Vec3D::new(-0.5, 0.2, 3.0),
+                &m,
+                width,
+                height,
+            )
+            .unwrap();
+            assert!(near_depth(a), "{:?}", a);
+            assert!(a.x.abs() < 1e4 && a.y.abs() < 1e4, "{:?}", a);
+            assert!((b.z - 0.9667).abs() < 1e-3 && b.x < 100.0);
+
+            // Direction does not matter
+            let (b2, a2) = clip_line(
+                &Vec3D::new(-0.5, 0.2, 3.0),
+                &Vec3D::new(1.0, 0.5, behind),
+                &m,
+                width,
+                height,
+            )
+            .unwrap();
+            assert!((a2 - a).len() < 1e-3 && (b2 - b).len() < 1e-3);
+        }
+
+        let behind = clip_line(
+            &Vec3D::new(1.0, 0.0, -1.0),
+            &Vec3D::new(0.0, 1.0, 0.05),
+            &m,
+            width,
+            height,
+        );
+        assert_eq!(behind, None);
+    }
+}
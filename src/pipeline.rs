@@ -0,0 +1,1085 @@
+//! Software render pipeline
+//!
+//! Runs vertex shader, culling, rasterization, depth test
+//! and fragment shader for every triangle of a mesh, then
+//! draws lines or points selected by the render mode
+//!
+
+use crate::bounds::{Frustum, Intersection};
+use crate::draw_3d::{Material, Mesh, Vec3D, Vec4D};
+use crate::framebuffer::{Framebuffer, RenderTarget, TILE_SIZE};
+use crate::profile::{Stage, StageTimes};
+use crate::raster::{self, Bounds, RasterVertex, SampleCoverage, Varying};
+use crate::shader::{FragmentShader, VertexInput, VertexShader};
+use crate::transform;
+use sdl2::pixels::Color;
+use std::cmp::Ordering;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+/// Triangle counts of a draw call
+///
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub total: usize,
+    /// Degenerate, culled by face orientation or outside of near/far planes
+    pub culled: usize,
+    pub rasterized: usize,
+    /// Meshes skipped whole because they are outside of view frustum
+    pub meshes_culled: usize,
+    /// Time spent in pipeline stages
+    pub times: StageTimes,
+}
+
+/// Which faces are skipped before rasterization
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CullMode {
+    Back,
+    Front,
+    /// Both sides are drawn, for open meshes
+    None,
+}
+
+impl CullMode {
+    pub fn next(self) -> CullMode {
+        match self {
+            CullMode::Back => CullMode::Front,
+            CullMode::Front => CullMode::None,
+            CullMode::None => CullMode::Back,
+        }
+    }
+}
+
+/// Vertex order of front faces as seen on screen
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Winding {
+    CounterClockwise,
+    Clockwise,
+}
+
+impl Winding {
+    pub fn toggle(self) -> Winding {
+        match self {
+            Winding::CounterClockwise => Winding::Clockwise,
+            Winding::Clockwise => Winding::CounterClockwise,
+        }
+    }
+}
+
+/// Face orientation settings of the pipeline
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Faces {
+    pub cull_mode: CullMode,
+    pub front_face: Winding,
+    /// Back faces which are not culled get flipped normal,
+    /// so they are lit like front faces
+    pub two_sided_lighting: bool,
+}
+
+impl Default for Faces {
+    fn default() -> Self {
+        Faces {
+            cull_mode: CullMode::Back,
+            front_face: Winding::CounterClockwise,
+            two_sided_lighting: false,
+        }
+    }
+}
+
+/// What the pipeline draws for every triangle
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    /// Filled and shaded
+    Solid,
+    /// All edges, including hidden ones and those of back faces
+    Wireframe,
+    /// Solid with visible edges drawn over it
+    Overlay,
+    /// Visible edges only, faces are filled with the background
+    HiddenLine,
+    /// Vertices only, of back faces too
+    Points,
+    /// Solid with face normals drawn as short lines
+    Normals,
+}
+
+impl RenderMode {
+    pub fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Solid => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::Overlay,
+            RenderMode::Overlay => RenderMode::HiddenLine,
+            RenderMode::HiddenLine => RenderMode::Points,
+            RenderMode::Points => RenderMode::Normals,
+            RenderMode::Normals => RenderMode::Solid,
+        }
+    }
+
+    /// Whether triangles are rasterized, hidden-line
+    /// mode only writes their depth
+    ///
+    fn fills(self) -> bool {
+        !matches!(self, RenderMode::Wireframe | RenderMode::Points)
+    }
+}
+
+/// Screen space triangle waiting for rasterization
+///
+type ScreenTriangle<V> = ([RasterVertex<V>; 3], Material);
+
+/// Meshes smaller than this are transformed on a single thread,
+/// spawning threads would take longer
+///
+const MIN_PARALLEL_TRIANGLES: usize = 4096;
+
+/// Lines are visible up to this much (relative to distance)
+/// behind the surface, so edges are not hidden by faces they lie on
+///
+const LINE_DEPTH_TOLERANCE: f32 = 2e-3;
+
+/// Side of the square drawn for every vertex in points mode, in pixels
+///
+const POINT_SIZE: i32 = 3;
+
+/// Length of drawn normals relative to mesh bounding sphere radius
+///
+const NORMAL_LENGTH: f32 = 0.05;
+
+/// Result of vertex stage for a part of the mesh
+///
+struct VertexOutput<V: Varying> {
+    opaque: Vec<ScreenTriangle<V>>,
+    transparent: Vec<ScreenTriangle<V>>,
+    /// Mesh indices of triangles which were not culled
+    drawn: Vec<usize>,
+    culled: usize,
+}
+
+pub struct Pipeline {
+    /// Algorithm used for filling triangles. Multisampled
+    /// framebuffers are always filled using edge functions
+    pub rasterizer: raster::Rasterizer,
+    /// Threads rasterizing framebuffer tiles, 1 rasterizes whole
+    /// triangles on the calling thread. Image is the same either way
+    pub threads: usize,
+    pub faces: Faces,
+    pub mode: RenderMode,
+    /// Color of edges, points and normals
+    pub line_color: Color,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline {
+            rasterizer: raster::Rasterizer::EdgeFunction,
+            threads: Pipeline::available_threads(),
+            faces: Faces::default(),
+            mode: RenderMode::Solid,
+            line_color: Color::RGB(255, 255, 255),
+        }
+    }
+
+    /// Number of threads the system can run in parallel
+    ///
+    pub fn available_threads() -> usize {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    }
+
+    /// Draws mesh using given shaders
+    ///
+    /// Triangles with any vertex behind the camera or outside
+    /// of near/far planes are skipped (there is no clipping yet)
+    ///
+    /// Meshes outside of view frustum are skipped without running
+    /// the vertex shader, if it provides `clip_matrix`. For meshes
+    /// with BVH only triangles in visible nodes are processed
+    ///
+    /// Modes which do not fill triangles draw back faces too,
+    /// `faces.cull_mode` is ignored for them
+    ///
+    /// Opaque triangles are drawn first, transparent ones are then
+    /// sorted back to front and blended over them without writing
+    /// depth. Sorting is done per mesh, not across draw calls
+    ///
+    /// With more than one thread, vertices of large meshes are transformed
+    /// in parallel chunks, triangles are binned into framebuffer
+    /// tiles and tiles are rasterized in parallel. Every tile gets its
+    /// triangles in the same order as the single-threaded path
+    ///
+    pub fn draw_mesh<VS, FS>(
+        &mut self,
+        mesh: &Mesh,
+        uniforms: &VS::Uniforms,
+        vertex_shader: &VS,
+        fragment_shader: &FS,
+        target: &mut Framebuffer,
+    ) -> RenderStats
+    where
+        VS: VertexShader + Sync,
+        VS::Uniforms: Sync,
+        VS::Varyings: Send + Sync,
+        FS: FragmentShader<VS::Uniforms, VS::Varyings> + Sync,
+    {
+        let width = target.render_width();
+        let height = target.render_height();
+        let mut faces = self.faces;
+        if !self.mode.fills() {
+            faces.cull_mode = CullMode::None;
+        }
+        let mut times = StageTimes::default();
+        let mut clock = Instant::now();
+
+        let mut visible = None;
+        if let Some(m) = vertex_shader.clip_matrix(uniforms) {
+            let frustum = Frustum::from_matrix(&m);
+            // Sphere test is cheaper, box is tighter for long objects
+            if frustum.test_sphere(&mesh.sphere) == Intersection::Outside
+                || frustum.test_aabb(&mesh.aabb) == Intersection::Outside
+            {
+                times.lap(Stage::Cull, clock);
+                return RenderStats {
+                    total: mesh.tris.len(),
+                    culled: mesh.tris.len(),
+                    meshes_culled: 1,
+                    times,
+                    ..Default::default()
+                };
+            }
+
+            if let Some(bvh) = &mesh.bvh {
+                let mut indices = Vec::new();
+                bvh.query_frustum(&frustum, |i| indices.push(i));
+                // Back to mesh order, drawing order must not depend on the tree
+                indices.sort_unstable();
+                visible = Some(indices);
+            }
+        }
+        let visible = visible.as_deref();
+        let count = visible.map_or(mesh.tris.len(), |v| v.len());
+        clock = times.lap(Stage::Cull, clock);
+
+        // Chunks are processed in parallel and joined in order,
+        // so triangles keep their order in the mesh
+        let threads = self.threads.max(1);
+        let chunk_size = count.div_ceil(threads).max(1);
+        let chunks = if threads > 1 && count >= MIN_PARALLEL_TRIANGLES {
+            thread::scope(|scope| {
+                let handles: Vec<_> = (0..count)
+                    .step_by(chunk_size)
+                    .map(|start| {
+                        let range = start..(start + chunk_size).min(count);
+                        scope.spawn(move || {
+                            vertex_stage(
+                                mesh,
+                                visible,
+                                range,
+                                uniforms,
+                                vertex_shader,
+                                faces,
+                                (width, height),
+                            )
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .collect()
+            })
+        } else {
+            vec![vertex_stage(
+                mesh,
+                visible,
+                0..count,
+                uniforms,
+                vertex_shader,
+                faces,
+                (width, height),
+            )]
+        };
+
+        let mut stats = RenderStats {
+            total: mesh.tris.len(),
+            culled: mesh.tris.len() - count,
+            ..Default::default()
+        };
+        let mut tris = Vec::new();
+        let mut transparent = Vec::new();
+        let mut drawn = Vec::new();
+        for mut chunk in chunks {
+            stats.culled += chunk.culled;
+            stats.rasterized += chunk.opaque.len() + chunk.transparent.len();
+            tris.append(&mut chunk.opaque);
+            transparent.append(&mut chunk.transparent);
+            drawn.append(&mut chunk.drawn);
+        }
+        clock = times.lap(Stage::Vertex, clock);
+
+        // Farthest first, so nearer triangles end up blended over them
+        let depth = |verts: &[RasterVertex<VS::Varyings>; 3]| {
+            verts[0].pos.z + verts[1].pos.z + verts[2].pos.z
+        };
+        transparent.sort_by(|a, b| {
+            depth(&b.0)
+                .partial_cmp(&depth(&a.0))
+                .unwrap_or(Ordering::Equal)
+        });
+        tris.append(&mut transparent);
+        clock = times.lap(Stage::Sort, clock);
+
+        if self.mode.fills() {
+            if self.threads > 1 {
+                self.fill_tiles(&tris, uniforms, fragment_shader, target);
+            } else {
+                let bounds = Bounds::screen(width, height);
+                for (verts, material) in &tris {
+                    self.fill(verts, material, bounds, uniforms, fragment_shader, target);
+                }
+            }
+        }
+        clock = times.lap(Stage::Raster, clock);
+        self.draw_lines(mesh, &tris, &drawn, uniforms, vertex_shader, target);
+        times.lap(Stage::Lines, clock);
+
+        stats.times = times;
+        stats
+    }
+
+    /// Draws edges, points or normals of the render mode on the
+    /// calling thread, after triangles were filled
+    ///
+    /// Lines are one rendered pixel wide, scaled up for supersampling
+    /// so they keep their width when presented. Only depth of filled
+    /// triangles hides them, lines do not write depth
+    ///
+    fn draw_lines<VS: VertexShader>(
+        &self,
+        mesh: &Mesh,
+        tris: &[ScreenTriangle<VS::Varyings>],
+        drawn: &[usize],
+        uniforms: &VS::Uniforms,
+        vertex_shader: &VS,
+        target: &mut Framebuffer,
+    ) {
+        let (width, height) = (target.render_width(), target.render_height());
+        let bounds = Bounds::screen(width, height);
+        let scale = width / target.width().max(1);
+        let color = self.line_color;
+
+        // Square of `size` rendered pixels centered at (x, y)
+        let mut plot = |x: i32, y: i32, size: i32, depth: f32, depth_test: bool| {
+            let (x0, y0) = (x - (size - 1) / 2, y - (size - 1) / 2);
+            for py in y0.max(0)..(y0 + size).min(height) {
+                for px in x0.max(0)..(x0 + size).min(width) {
+                    for s in 0..target.samples() {
+                        let stored = target.depth(px, py, s);
+                        if !depth_test
+                            || 1.0 - depth >= (1.0 - stored) * (1.0 - LINE_DEPTH_TOLERANCE)
+                        {
+                            target.set_sample(px, py, s, color, stored);
+                        }
+                    }
+                }
+            }
+        };
+
+        match self.mode {
+            RenderMode::Solid => {}
+            RenderMode::Wireframe | RenderMode::Overlay | RenderMode::HiddenLine => {
+                let depth_test = self.mode != RenderMode::Wireframe;
+                for (verts, _) in tris {
+                    for i in 0..3 {
+                        let (a, b) = (verts[i].pos, verts[(i + 1) % 3].pos);
+                        raster::line(a, b, bounds, |x, y, depth| {
+                            plot(x, y, scale, depth, depth_test)
+                        });
+                    }
+                }
+            }
+            RenderMode::Points => {
+                for (verts, _) in tris {
+                    for v in verts {
+                        let (x, y) = (v.pos.x.floor() as i32, v.pos.y.floor() as i32);
+                        plot(x, y, POINT_SIZE * scale, v.pos.z, false);
+                    }
+                }
+            }
+            RenderMode::Normals => {
+                let length = mesh.sphere.radius * NORMAL_LENGTH;
+                for &i in drawn {
+                    let tri = &mesh.tris[i];
+                    let normal = match (tri.p[1] - tri.p[0])
+                        .cross_product(&(tri.p[2] - tri.p[0]))
+                        .try_normalize()
+                    {
+                        Some(normal) => normal,
+                        None => continue,
+                    };
+                    let center = (tri.p[0] + tri.p[1] + tri.p[2]) / 3.0;
+                    let screen = |pos: Vec3D| {
+                        let input = VertexInput {
+                            pos,
+                            normal,
+                            material: mesh.material(tri),
+                        };
+                        let (clip, varyings) = vertex_shader.vertex(uniforms, &input);
+                        to_screen(&clip, varyings, width, height)
+                    };
+                    if let (Some(a), Some(b)) = (screen(center), screen(center + normal * length)) {
+                        raster::line(a.pos, b.pos, bounds, |x, y, depth| {
+                            plot(x, y, scale, depth, true)
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bins triangles into framebuffer tiles and rasterizes
+    /// the tiles on `self.threads` threads
+    ///
+    fn fill_tiles<U, V, FS>(
+        &self,
+        tris: &[ScreenTriangle<V>],
+        uniforms: &U,
+        fragment_shader: &FS,
+        target: &mut Framebuffer,
+    ) where
+        U: Sync,
+        V: Varying + Send + Sync,
+        FS: FragmentShader<U, V> + Sync,
+    {
+        let screen = Bounds::screen(target.render_width(), target.render_height());
+        let tiles_x = (screen.max_x + TILE_SIZE - 1) / TILE_SIZE;
+        let mut tiles = target.tiles_mut();
+
+        // Bins keep triangles in drawing order
+        let mut bins = vec![Vec::new(); tiles.len()];
+        for (i, (verts, _)) in tris.iter().enumerate() {
+            let b = screen.triangle(verts);
+            if b.is_empty() {
+                continue;
+            }
+            for ty in b.min_y / TILE_SIZE..=(b.max_y - 1) / TILE_SIZE {
+                for tx in b.min_x / TILE_SIZE..=(b.max_x - 1) / TILE_SIZE {
+                    bins[(ty * tiles_x + tx) as usize].push(i);
+                }
+            }
+        }
+
+        // Threads take tiles one by one, so busy
+        // tiles do not hold up the others
+        let work = Mutex::new(
+            tiles
+                .iter_mut()
+                .zip(bins.iter())
+                .filter(|(_, bin)| !bin.is_empty()),
+        );
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(|| loop {
+                    let next = work.lock().unwrap().next();
+                    let (tile, bin) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    let bounds = tile.bounds;
+                    for &i in bin {
+                        let (verts, material) = &tris[i];
+                        self.fill(verts, material, bounds, uniforms, fragment_shader, tile);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Rasterizes and shades part of screen space triangle inside `bounds`
+    ///
+    /// Opaque materials write color and depth of the samples passing
+    /// depth test, transparent ones only blend their color. In hidden-line
+    /// mode opaque materials write depth only
+    ///
+    fn fill<U, V, FS, T>(
+        &self,
+        verts: &[RasterVertex<V>; 3],
+        material: &Material,
+        bounds: Bounds,
+        uniforms: &U,
+        fragment_shader: &FS,
+        target: &mut T,
+    ) where
+        V: Varying,
+        FS: FragmentShader<U, V>,
+        T: RenderTarget,
+    {
+        let samples = target.samples();
+        let transparent = material.is_transparent();
+        let alpha = material.opacity.clamp(0.0, 1.0);
+        let depth_only = self.mode == RenderMode::HiddenLine;
+
+        let mut shade = |frag: raster::Fragment<V>, coverage: &SampleCoverage| {
+            // Depth test each covered sample, shade once if any passes
+            let mut passed = 0u32;
+            for s in 0..samples {
+                if coverage.mask & (1 << s) != 0
+                    && coverage.depths[s] < target.depth(frag.x, frag.y, s)
+                {
+                    passed |= 1 << s;
+                }
+            }
+            if passed == 0 {
+                return;
+            }
+            if depth_only {
+                for s in 0..samples {
+                    if passed & (1 << s) != 0 && !transparent {
+                        target.set_depth(frag.x, frag.y, s, coverage.depths[s]);
+                    }
+                }
+                return;
+            }
+            if let Some(mut color) = fragment_shader.fragment(uniforms, &frag) {
+                if transparent {
+                    color.a = (color.a as f32 * alpha).round() as u8;
+                }
+                for s in 0..samples {
+                    if passed & (1 << s) == 0 {
+                        continue;
+                    }
+                    if transparent {
+                        target.blend_sample(frag.x, frag.y, s, color, material.blend);
+                    } else {
+                        target.set_sample(frag.x, frag.y, s, color, coverage.depths[s]);
+                    }
+                }
+            }
+        };
+
+        if samples > 1 {
+            raster::triangle_multisample(verts, bounds, samples, shade);
+        } else {
+            raster::rasterize(self.rasterizer, verts, bounds, |frag| {
+                let mut coverage = SampleCoverage {
+                    mask: 1,
+                    depths: [0.0; raster::MAX_SAMPLES],
+                };
+                coverage.depths[0] = frag.depth;
+                shade(frag, &coverage)
+            });
+        }
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::new()
+    }
+}
+
+/// Runs vertex shader on triangles `range` of the mesh and culls
+/// triangles which should not be rasterized
+///
+/// With `visible` given, `range` indexes into it instead of `mesh.tris`.
+/// Back faces lit from both sides run the vertex shader again
+/// with flipped normal
+///
+/// Shaders providing `clip_matrix` get positions of the whole range
+/// transformed at once by `transform::mult_matrix_points` (SIMD),
+/// which gives exactly the same result as transforming them one by one
+///
+fn vertex_stage<VS: VertexShader>(
+    mesh: &Mesh,
+    visible: Option<&[usize]>,
+    range: Range<usize>,
+    uniforms: &VS::Uniforms,
+    vertex_shader: &VS,
+    faces: Faces,
+    (width, height): (i32, i32),
+) -> VertexOutput<VS::Varyings> {
+    let mut output = VertexOutput {
+        opaque: Vec::new(),
+        transparent: Vec::new(),
+        drawn: Vec::new(),
+        culled: 0,
+    };
+
+    let index = |i: usize| match visible {
+        Some(visible) => visible[i],
+        None => i,
+    };
+    let clips = vertex_shader.clip_matrix(uniforms).map(|m| {
+        let positions: Vec<Vec3D> = range
+            .clone()
+            .flat_map(|i| mesh.tris[index(i)].p.iter().copied())
+            .collect();
+        transform::mult_matrix_points(&positions, &m)
+    });
+    let start = range.start;
+
+    for i in range {
+        let index = index(i);
+        let tri = &mesh.tris[index];
+        // Clip positions of the triangle, if transformed in batch
+        let tri_clips = clips.as_ref().map(|clips| &clips[(i - start) * 3..][..3]);
+        let line1 = tri.p[1] - tri.p[0];
+        let line2 = tri.p[2] - tri.p[0];
+
+        // Skip degenerate triangles, they have no normal
+        let normal = match line1.cross_product(&line2).try_normalize() {
+            Some(normal) => normal,
+            None => {
+                output.culled += 1;
+                continue;
+            }
+        };
+        let material = mesh.material(tri);
+
+        let shade = |normal: Vec3D| {
+            let screen = |v: usize| {
+                let input = VertexInput {
+                    pos: tri.p[v],
+                    normal,
+                    material,
+                };
+                let (clip, varyings) = match tri_clips {
+                    Some(clips) => (clips[v], vertex_shader.varyings(uniforms, &input)),
+                    None => vertex_shader.vertex(uniforms, &input),
+                };
+                to_screen(&clip, varyings, width, height)
+            };
+            match (screen(0), screen(1), screen(2)) {
+                (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                _ => None,
+            }
+        };
+        let mut verts = match shade(normal) {
+            Some(verts) => verts,
+            None => {
+                output.culled += 1;
+                continue;
+            }
+        };
+
+        // Screen y points down, so counter-clockwise triangles
+        // have negative signed area. Triangles seen edge-on are skipped
+        let a = verts[1].pos - verts[0].pos;
+        let b = verts[2].pos - verts[0].pos;
+        let area = a.x * b.y - a.y * b.x;
+        let front = match faces.front_face {
+            Winding::CounterClockwise => area < 0.0,
+            Winding::Clockwise => area > 0.0,
+        };
+        let culled = match faces.cull_mode {
+            CullMode::Back => !front,
+            CullMode::Front => front,
+            CullMode::None => false,
+        };
+        if area == 0.0 || culled {
+            output.culled += 1;
+            continue;
+        }
+        if !front && faces.two_sided_lighting {
+            // Same positions, only varyings change
+            if let Some(flipped) = shade(-normal) {
+                verts = flipped;
+            }
+        }
+
+        output.drawn.push(index);
+        if material.is_transparent() {
+            output.transparent.push((verts, material));
+        } else {
+            output.opaque.push((verts, material));
+        }
+    }
+
+    output
+}
+
+/// Clip space -> screen space, `None` if vertex is behind
+/// the camera or outside of near/far planes
+///
+fn to_screen<V: Varying>(
+    clip: &Vec4D,
+    varyings: V,
+    width: i32,
+    height: i32,
+) -> Option<RasterVertex<V>> {
+    if clip.w <= f32::EPSILON {
+        return None;
+    }
+
+    let ndc = clip.perspective_divide();
+    if ndc.z < 0.0 || ndc.z > 1.0 {
+        return None;
+    }
+
+    Some(RasterVertex::new(
+        Vec3D::new(
+            (ndc.x + 1.0) * width as f32 * 0.5,
+            (ndc.y + 1.0) * height as f32 * 0.5,
+            ndc.z,
+        ),
+        1.0 / clip.w,
+        varyings,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::grid_mesh;
+    use crate::draw::Blend;
+    use crate::draw_3d::Triangle3D;
+    use crate::framebuffer::Antialiasing;
+    use crate::shader::{FlatShader, SceneUniforms};
+    use crate::transform::Mat4x4;
+
+    /// Renders the mesh with 1 and 4 threads, images have to be the same
+    ///
+    fn assert_same_image(mesh: &Mesh, antialiasing: Antialiasing) {
+        let uniforms = SceneUniforms::new(
+            Mat4x4::mat_rot_y(&0.2),
+            Mat4x4::id(),
+            Mat4x4::mat_proj(90.0, 0.5, 1000.0, 0.1),
+            Vec3D::new(0.3, -0.4, 1.0).normalize(),
+        );
+        let images: Vec<Vec<u32>> = [1, 4]
+            .iter()
+            .map(|&threads| {
+                let mut pipeline = Pipeline::new();
+                pipeline.threads = threads;
+                let mut framebuffer = Framebuffer::new(150, 75, antialiasing);
+                framebuffer.clear(Color::RGB(0, 0, 0));
+                let stats =
+                    pipeline.draw_mesh(mesh, &uniforms, &FlatShader, &FlatShader, &mut framebuffer);
+                assert!(stats.rasterized > MIN_PARALLEL_TRIANGLES);
+                framebuffer.colors().to_vec()
+            })
+            .collect();
+
+        assert!(images[0].iter().any(|&c| c != images[0][0]));
+        assert!(images[0] == images[1], "{:?}", antialiasing);
+    }
+
+    /// Same as `FlatShader` without `clip_matrix`, so the
+    /// pipeline neither culls meshes nor BVH nodes
+    ///
+    struct Unculled;
+
+    impl VertexShader for Unculled {
+        type Uniforms = SceneUniforms;
+        type Varyings = (f32, Vec3D);
+
+        fn vertex(&self, uniforms: &SceneUniforms, input: &VertexInput) -> (Vec4D, (f32, Vec3D)) {
+            FlatShader.vertex(uniforms, input)
+        }
+    }
+
+    #[test]
+    fn frustum_culling_same_image() {
+        let mut mesh = grid_mesh(40);
+        mesh.build_bvh();
+        // Partly off the left edge and off screen entirely
+        for &(x, partly) in &[(2.5, true), (8.0, false)] {
+            let uniforms = SceneUniforms::new(
+                Mat4x4::mat_rot_y(&0.2) * Mat4x4::mat_trans(x, 0.3, 0.0),
+                Mat4x4::id(),
+                Mat4x4::mat_proj(90.0, 0.5, 1000.0, 0.1),
+                Vec3D::new(0.3, -0.4, 1.0).normalize(),
+            );
+            let mut pipeline = Pipeline::new();
+            pipeline.threads = 1;
+            let mut render = |culled: bool| {
+                let mut framebuffer = Framebuffer::new(150, 75, Antialiasing::None);
+                framebuffer.clear(Color::RGB(0, 0, 0));
+                let stats = if culled {
+                    pipeline.draw_mesh(&mesh, &uniforms, &FlatShader, &FlatShader, &mut framebuffer)
+                } else {
+                    pipeline.draw_mesh(&mesh, &uniforms, &Unculled, &FlatShader, &mut framebuffer)
+                };
+                (stats, framebuffer.colors().to_vec())
+            };
+
+            let (culled_stats, culled) = render(true);
+            let (stats, image) = render(false);
+            assert!(culled == image, "x = {}", x);
+            assert_eq!(stats.meshes_culled, 0);
+            assert!(culled_stats.culled > stats.culled);
+            if partly {
+                assert!(image.iter().any(|&c| c != image[0]));
+                assert_eq!(culled_stats.meshes_culled, 0);
+            } else {
+                assert_eq!(culled_stats.meshes_culled, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn cull_mode_and_winding() {
+        let uniforms = SceneUniforms::new(
+            Mat4x4::mat_trans(0.0, 0.0, 2.0),
+            Mat4x4::id(),
+            Mat4x4::mat_proj(90.0, 1.0, 100.0, 0.1),
+            Vec3D::new(0.0, 0.0, 1.0),
+        );
+        // Screen y points down, so right and then down is clockwise
+        let clockwise = Triangle3D::new(
+            Vec3D::new(-0.5, -0.5, 0.0),
+            Vec3D::new(0.5, -0.5, 0.0),
+            Vec3D::new(-0.5, 0.5, 0.0),
+        );
+        let counter_clockwise = Triangle3D::new(clockwise.p[0], clockwise.p[2], clockwise.p[1]);
+
+        let drawn = |tri: Triangle3D, cull_mode: CullMode, front_face: Winding| {
+            let mut pipeline = Pipeline::new();
+            pipeline.threads = 1;
+            pipeline.faces.cull_mode = cull_mode;
+            pipeline.faces.front_face = front_face;
+            let mut framebuffer = Framebuffer::new(64, 64, Antialiasing::None);
+            framebuffer.clear(Color::RGB(0, 0, 0));
+            let mesh = Mesh::new(vec![tri]);
+            let stats =
+                pipeline.draw_mesh(&mesh, &uniforms, &FlatShader, &FlatShader, &mut framebuffer);
+
+            let covered = framebuffer
+                .colors()
+                .iter()
+                .filter(|&&c| c != framebuffer.colors()[0]);
+            assert_eq!(covered.count() > 0, stats.rasterized == 1);
+            assert_eq!(stats.culled + stats.rasterized, 1);
+            stats.rasterized == 1
+        };
+
+        use CullMode::*;
+        use Winding::*;
+        for &(cull_mode, front_face, cw, ccw) in &[
+            (Back, CounterClockwise, false, true),
+            (Back, Clockwise, true, false),
+            (Front, CounterClockwise, true, false),
+            (Front, Clockwise, false, true),
+            (CullMode::None, CounterClockwise, true, true),
+            (CullMode::None, Clockwise, true, true),
+        ] {
+            let case = format!("{:?} {:?}", cull_mode, front_face);
+            assert_eq!(drawn(clockwise, cull_mode, front_face), cw, "{}", case);
+            assert_eq!(
+                drawn(counter_clockwise, cull_mode, front_face),
+                ccw,
+                "{}",
+                case
+            );
+        }
+    }
+
+    /// Two squares facing the camera, the nearer one hides the left
+    /// edge of the farther one, and a square facing away on the left
+    ///
+    fn mode_scene() -> Mesh {
+        // Counter-clockwise on screen unless `back`
+        let quad = |x0: f32, x1: f32, y0: f32, y1: f32, z: f32, back: bool| {
+            let p = |x, y| Vec3D::new(x, y, z);
+            let mut tris = vec![
+                Triangle3D::new(p(x0, y0), p(x0, y1), p(x1, y0)),
+                Triangle3D::new(p(x1, y0), p(x0, y1), p(x1, y1)),
+            ];
+            if back {
+                for tri in &mut tris {
+                    tri.p.swap(1, 2);
+                }
+            }
+            tris
+        };
+        let mut tris = quad(-0.5, 0.5, -0.5, 0.5, 2.0, false);
+        tris.append(&mut quad(-0.4, 2.0, -0.4, 0.4, 4.0, false));
+        tris.append(&mut quad(-2.0, -1.2, -0.4, 0.4, 2.0, true));
+        Mesh::new(tris)
+    }
+
+    /// Renders `mode_scene` into 100 x 100 pixels, the near square covers
+    /// pixels 37.5 to 62.5 in both axes, the far one x from 45 to 75
+    /// and y from 45 to 55, the back facing one x from 0 to 20 and
+    /// y from 40 to 60
+    ///
+    fn render_mode(mode: RenderMode) -> Framebuffer {
+        let uniforms = SceneUniforms::new(
+            Mat4x4::id(),
+            Mat4x4::id(),
+            Mat4x4::mat_proj(90.0, 1.0, 100.0, 0.1),
+            Vec3D::new(0.0, 0.0, 1.0),
+        );
+        let mut pipeline = Pipeline::new();
+        pipeline.threads = 1;
+        pipeline.mode = mode;
+        pipeline.line_color = LINE;
+        let mut framebuffer = Framebuffer::new(100, 100, Antialiasing::None);
+        framebuffer.clear(BACKGROUND);
+        pipeline.draw_mesh(
+            &mode_scene(),
+            &uniforms,
+            &FlatShader,
+            &FlatShader,
+            &mut framebuffer,
+        );
+        framebuffer
+    }
+
+    const LINE: Color = Color {
+        r: 255,
+        g: 0,
+        b: 255,
+        a: 255,
+    };
+    const BACKGROUND: Color = Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+
+    /// Inside the near square, away from its edges
+    const FACE: (i32, i32) = (40, 45);
+    /// Left edge of the far square, behind the near one
+    const HIDDEN_EDGE: (i32, i32) = (45, 50);
+    /// Right edge of the far square
+    const VISIBLE_EDGE: (i32, i32) = (75, 50);
+    /// Left edge of the near square
+    const NEAR_EDGE: (i32, i32) = (37, 50);
+    /// Top edge of the back facing square
+    const BACK_EDGE: (i32, i32) = (10, 40);
+
+    fn color(framebuffer: &Framebuffer, (x, y): (i32, i32)) -> Color {
+        framebuffer.resolve(x, y)
+    }
+
+    fn lines(framebuffer: &Framebuffer) -> Vec<(i32, i32)> {
+        let mut lines = Vec::new();
+        for y in 0..framebuffer.height() {
+            for x in 0..framebuffer.width() {
+                if framebuffer.resolve(x, y) == LINE {
+                    lines.push((x, y));
+                }
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn mode_solid() {
+        let image = render_mode(RenderMode::Solid);
+        assert!(lines(&image).is_empty());
+        assert_ne!(color(&image, FACE), BACKGROUND);
+        assert_eq!(color(&image, BACK_EDGE), BACKGROUND);
+    }
+
+    #[test]
+    fn mode_wireframe() {
+        let image = render_mode(RenderMode::Wireframe);
+        for &edge in &[HIDDEN_EDGE, VISIBLE_EDGE, NEAR_EDGE, BACK_EDGE] {
+            assert_eq!(color(&image, edge), LINE, "{:?}", edge);
+        }
+        assert_eq!(color(&image, FACE), BACKGROUND);
+    }
+
+    #[test]
+    fn mode_overlay() {
+        let image = render_mode(RenderMode::Overlay);
+        for &edge in &[VISIBLE_EDGE, NEAR_EDGE] {
+            assert_eq!(color(&image, edge), LINE, "{:?}", edge);
+        }
+        assert_eq!(color(&image, HIDDEN_EDGE), color(&image, FACE));
+        assert_eq!(color(&image, BACK_EDGE), BACKGROUND);
+        assert!(![BACKGROUND, LINE].contains(&color(&image, FACE)));
+    }
+
+    #[test]
+    fn mode_hidden_line() {
+        let image = render_mode(RenderMode::HiddenLine);
+        for &edge in &[VISIBLE_EDGE, NEAR_EDGE] {
+            assert_eq!(color(&image, edge), LINE, "{:?}", edge);
+        }
+        for &pixel in &[HIDDEN_EDGE, FACE, BACK_EDGE] {
+            assert_eq!(color(&image, pixel), BACKGROUND, "{:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn mode_points() {
+        let image = render_mode(RenderMode::Points);
+        // Window positions of vertices, back facing ones included
+        let mut vertices = Vec::new();
+        for &(x0, x1, y0, y1) in &[
+            (37.5, 62.5, 37.5, 62.5),
+            (45.0, 75.0, 45.0, 55.0),
+            (0.0, 20.0, 40.0, 60.0),
+        ] {
+            for &x in &[x0, x1] {
+                for &y in &[y0, y1] {
+                    vertices.push((x as i32, y as i32));
+                }
+            }
+        }
+
+        // Points are 3 pixels wide, vertices on pixel borders may round
+        // to the pixel before
+        let near =
+            |p: (i32, i32), v: (i32, i32), d: i32| (p.0 - v.0).abs() <= d && (p.1 - v.1).abs() <= d;
+        let lines = lines(&image);
+        for &v in &vertices {
+            assert!(lines.iter().any(|&p| near(p, v, 1)), "{:?}", v);
+        }
+        for &p in &lines {
+            assert!(vertices.iter().any(|&v| near(p, v, 2)), "{:?}", p);
+        }
+        assert_eq!(color(&image, FACE), BACKGROUND);
+    }
+
+    #[test]
+    fn mode_normals() {
+        let solid = render_mode(RenderMode::Solid);
+        let image = render_mode(RenderMode::Normals);
+        let lines = lines(&image);
+        assert!(!lines.is_empty());
+        // Lines start at centers of drawn triangles, the rest is solid
+        assert!(lines.iter().all(|&(x, _)| x > 20));
+        for y in 0..100 {
+            for x in 0..100 {
+                if !lines.contains(&(x, y)) {
+                    assert_eq!(image.resolve(x, y), solid.resolve(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn threads_same_image() {
+        assert_same_image(&grid_mesh(60), Antialiasing::None);
+    }
+
+    #[test]
+    fn threads_same_image_msaa() {
+        assert_same_image(&grid_mesh(60), Antialiasing::Msaa(4));
+    }
+
+    #[test]
+    fn threads_same_image_transparent() {
+        let mut mesh = grid_mesh(60);
+        mesh.materials
+            .push(Material::new(Color::RGB(255, 0, 0), 0.5, Blend::Alpha));
+        for (i, tri) in mesh.tris.iter_mut().enumerate() {
+            if i % 3 == 0 {
+                tri.material = 1;
+            }
+        }
+        assert_same_image(&mesh, Antialiasing::None);
+        assert_same_image(&mesh, Antialiasing::Msaa(4));
+    }
+}
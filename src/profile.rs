@@ -0,0 +1,455 @@
+//! Per-stage frame profiler
+//!
+//! Stages are timed with `Instant` laps, so instrumentation costs
+//! a clock read per stage. Profiler keeps rolling averages over
+//! recent frames and can dump every frame to CSV or JSON
+//!
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::{Index, IndexMut};
+use std::time::{Duration, Instant};
+
+/// Timed part of a frame
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    /// Input handling
+    Events,
+    /// Fixed steps, camera and matrices
+    Update,
+    /// Framebuffer clear
+    Clear,
+    /// Frustum and BVH culling of the mesh
+    Cull,
+    /// Vertex shader, face culling and joining of chunks
+    Vertex,
+    /// Sorting of transparent triangles
+    Sort,
+    /// Triangle filling, including tile binning
+    Raster,
+    /// Edges, points and normals of the render mode
+    Lines,
+    /// Framebuffer upload to the window texture
+    Present,
+    /// Debug drawing, HUD and swapping window buffers
+    Overlay,
+    /// Waiting for the frame rate cap
+    Idle,
+}
+
+impl Stage {
+    pub const COUNT: usize = 11;
+
+    pub const ALL: [Stage; Stage::COUNT] = [
+        Stage::Events,
+        Stage::Update,
+        Stage::Clear,
+        Stage::Cull,
+        Stage::Vertex,
+        Stage::Sort,
+        Stage::Raster,
+        Stage::Lines,
+        Stage::Present,
+        Stage::Overlay,
+        Stage::Idle,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Events => "events",
+            Stage::Update => "update",
+            Stage::Clear => "clear",
+            Stage::Cull => "cull",
+            Stage::Vertex => "vertex",
+            Stage::Sort => "sort",
+            Stage::Raster => "raster",
+            Stage::Lines => "lines",
+            Stage::Present => "present",
+            Stage::Overlay => "overlay",
+            Stage::Idle => "idle",
+        }
+    }
+}
+
+/// Time spent in every stage
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StageTimes([Duration; Stage::COUNT]);
+
+impl StageTimes {
+    /// Adds time since `clock` to the stage, returns current
+    /// time to be used as `clock` of the next stage
+    ///
+    pub fn lap(&mut self, stage: Stage, clock: Instant) -> Instant {
+        let now = Instant::now();
+        self[stage] += now - clock;
+        now
+    }
+
+    pub fn add(&mut self, other: &StageTimes) {
+        for (time, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *time += *other;
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.0.iter().sum()
+    }
+}
+
+impl Index<Stage> for StageTimes {
+    type Output = Duration;
+
+    fn index(&self, stage: Stage) -> &Duration {
+        &self.0[stage as usize]
+    }
+}
+
+impl IndexMut<Stage> for StageTimes {
+    fn index_mut(&mut self, stage: Stage) -> &mut Duration {
+        &mut self.0[stage as usize]
+    }
+}
+
+/// Collects stage times of frames
+///
+pub struct Profiler {
+    /// Times of the frame in progress
+    pub current: StageTimes,
+    /// Last `window` finished frames, newest last
+    recent: VecDeque<StageTimes>,
+    /// Sum of `recent`
+    sum: StageTimes,
+    window: usize,
+    /// Every finished frame if kept for dumping
+    frames: Option<Vec<StageTimes>>,
+}
+
+impl Profiler {
+    /// Profiler averaging over `window` frames, with `keep_frames`
+    /// all frames are stored for `dump`
+    ///
+    pub fn new(window: usize, keep_frames: bool) -> Profiler {
+        Profiler {
+            current: StageTimes::default(),
+            recent: VecDeque::with_capacity(window),
+            sum: StageTimes::default(),
+            window: window.max(1),
+            frames: if keep_frames { Some(Vec::new()) } else { None },
+        }
+    }
+
+    /// Adds time since `clock` to the stage of current frame,
+    /// see `StageTimes::lap`
+    ///
+    pub fn lap(&mut self, stage: Stage, clock: Instant) -> Instant {
+        self.current.lap(stage, clock)
+    }
+
+    /// Finishes current frame and starts a new one
+    ///
+    pub fn end_frame(&mut self) {
+        let frame = std::mem::take(&mut self.current);
+        if self.recent.len() == self.window {
+            if let Some(old) = self.recent.pop_front() {
+                for (sum, old) in self.sum.0.iter_mut().zip(old.0.iter()) {
+                    *sum -= *old;
+                }
+            }
+        }
+        self.sum.add(&frame);
+        self.recent.push_back(frame);
+        if let Some(frames) = &mut self.frames {
+            frames.push(frame);
+        }
+    }
+
+    /// Average time of the stage over recent frames
+    ///
+    pub fn average(&self, stage: Stage) -> Duration {
+        match self.recent.len() {
+            0 => Duration::ZERO,
+            n => self.sum[stage] / n as u32,
+        }
+    }
+
+    /// Table of average stage times and their share of the frame
+    ///
+    pub fn report(&self) -> String {
+        let total = self.sum.total().as_secs_f64().max(f64::EPSILON);
+        let frames = self.recent.len().max(1) as f64;
+        let mut report = format!("stage    avg ms (last {} frames)", self.recent.len());
+        for &stage in &Stage::ALL {
+            let time = self.sum[stage].as_secs_f64();
+            report.push_str(&format!(
+                "\n{:<8} {:>7.3} {:>5.1}%",
+                stage.name(),
+                time * 1000.0 / frames,
+                time * 100.0 / total,
+            ));
+        }
+        report.push_str(&format!(
+            "\n{:<8} {:>7.3}",
+            "frame",
+            total * 1000.0 / frames
+        ));
+        report
+    }
+
+    /// Writes kept frames as CSV, one row per frame in milliseconds
+    ///
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let names: Vec<&str> = Stage::ALL.iter().map(|stage| stage.name()).collect();
+        writeln!(out, "frame,{},total", names.join(","))?;
+        for (i, frame) in self.frames.iter().flatten().enumerate() {
+            write!(out, "{}", i)?;
+            for &stage in &Stage::ALL {
+                write!(out, ",{:.3}", ms(frame[stage]))?;
+            }
+            writeln!(out, ",{:.3}", ms(frame.total()))?;
+        }
+        Ok(())
+    }
+
+    /// Writes stage names, averages and kept frames as JSON,
+    /// times are in milliseconds
+    ///
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let names: Vec<String> = Stage::ALL
+            .iter()
+            .map(|stage| format!("\"{}\"", stage.name()))
+            .collect();
+        let averages: Vec<String> = Stage::ALL
+            .iter()
+            .map(|&stage| format!("{:.3}", ms(self.average(stage))))
+            .collect();
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"stages\": [{}],", names.join(", "))?;
+        writeln!(out, "  \"average_ms\": [{}],", averages.join(", "))?;
+        writeln!(out, "  \"frames_ms\": [")?;
+        let frames = self.frames.as_deref().unwrap_or(&[]);
+        for (i, frame) in frames.iter().enumerate() {
+            let times: Vec<String> = Stage::ALL
+                .iter()
+                .map(|&stage| format!("{:.3}", ms(frame[stage])))
+                .collect();
+            let comma = if i + 1 < frames.len() { "," } else { "" };
+            writeln!(out, "    [{}]{}", times.join(", "), comma)?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")
+    }
+
+    /// Writes kept frames to file, as JSON for `.json`
+    /// extension and as CSV otherwise
+    ///
+    pub fn dump(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        if path.ends_with(".json") {
+            self.write_json(&mut out)?;
+        } else {
+            self.write_csv(&mut out)?;
+        }
+        out.flush()
+    }
+}
+
+fn ms(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Profiler keeping 3 frames, stage `i` of frame `f` took
+    /// `f + i / 10` ms
+    ///
+    fn profiler() -> Profiler {
+        let mut profiler = Profiler::new(2, true);
+        for f in 0..3 {
+            for (i, &stage) in Stage::ALL.iter().enumerate() {
+                profiler.current[stage] = Duration::from_micros(f * 1000 + i as u64 * 100);
+            }
+            profiler.end_frame();
+        }
+        profiler
+    }
+
+    #[test]
+    fn csv() {
+        let mut out = Vec::new();
+        profiler().write_csv(&mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|row| row.split(',').collect()).collect();
+
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0][0], "frame");
+        assert_eq!(rows[0][Stage::COUNT + 1], "total");
+        for (i, &stage) in Stage::ALL.iter().enumerate() {
+            assert_eq!(rows[0][i + 1], stage.name());
+        }
+        for (f, row) in rows[1..].iter().enumerate() {
+            assert_eq!(row.len(), Stage::COUNT + 2);
+            assert_eq!(row[0], f.to_string());
+            assert_eq!(row[1], format!("{}.000", f));
+            assert_eq!(row[Stage::COUNT], format!("{}.000", f + 1));
+            assert_eq!(row[Stage::COUNT + 1], format!("{}.500", f * 11 + 5));
+        }
+    }
+
+    #[test]
+    fn csv_without_frames() {
+        let mut profiler = Profiler::new(2, false);
+        profiler.end_frame();
+        let mut out = Vec::new();
+        profiler.write_csv(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn json() {
+        let mut out = Vec::new();
+        profiler().write_json(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let json = Json::parse(&text).expect("valid JSON");
+
+        let stages = json.field("stages").array();
+        assert_eq!(stages.len(), Stage::COUNT);
+        for (name, &stage) in stages.iter().zip(Stage::ALL.iter()) {
+            assert_eq!(name, &Json::Str(stage.name().to_string()));
+        }
+
+        // Averages over the last 2 frames
+        let averages = json.field("average_ms").array();
+        assert_eq!(averages.len(), Stage::COUNT);
+        assert_eq!(averages[0], Json::Number(1.5));
+
+        let frames = json.field("frames_ms").array();
+        assert_eq!(frames.len(), 3);
+        for (f, frame) in frames.iter().enumerate() {
+            let frame = frame.array();
+            assert_eq!(frame.len(), Stage::COUNT);
+            assert_eq!(frame[1], Json::Number(f as f64 + 0.1));
+        }
+    }
+
+    #[test]
+    fn json_without_frames() {
+        let mut out = Vec::new();
+        Profiler::new(2, false).write_json(&mut out).unwrap();
+        let json = Json::parse(std::str::from_utf8(&out).unwrap()).expect("valid JSON");
+        assert!(json.field("frames_ms").array().is_empty());
+        assert_eq!(json.field("average_ms").array()[0], Json::Number(0.0));
+    }
+
+    /// Just enough JSON to read back what the profiler writes
+    ///
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Number(f64),
+        Str(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        /// Parses whole text, `None` if it is not a single valid value
+        ///
+        fn parse(text: &str) -> Option<Json> {
+            let mut rest = text;
+            let json = Json::value(&mut rest)?;
+            if rest.trim_start().is_empty() {
+                Some(json)
+            } else {
+                None
+            }
+        }
+
+        fn value(text: &mut &str) -> Option<Json> {
+            *text = text.trim_start();
+            let first = text.chars().next()?;
+            match first {
+                '"' => Json::string(text).map(Json::Str),
+                '[' => {
+                    let mut items = Vec::new();
+                    Json::list(text, ']', |text| {
+                        items.push(Json::value(text)?);
+                        Some(())
+                    })?;
+                    Some(Json::Array(items))
+                }
+                '{' => {
+                    let mut fields = Vec::new();
+                    Json::list(text, '}', |text| {
+                        *text = text.trim_start();
+                        let key = Json::string(text)?;
+                        *text = text.trim_start().strip_prefix(':')?;
+                        fields.push((key, Json::value(text)?));
+                        Some(())
+                    })?;
+                    Some(Json::Object(fields))
+                }
+                _ => {
+                    let end = text
+                        .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                        .unwrap_or(text.len());
+                    let number = text[..end].parse().ok()?;
+                    *text = &text[end..];
+                    Some(Json::Number(number))
+                }
+            }
+        }
+
+        /// Items separated by commas between opening bracket and `close`
+        ///
+        fn list<F>(text: &mut &str, close: char, mut item: F) -> Option<()>
+        where
+            F: FnMut(&mut &str) -> Option<()>,
+        {
+            *text = &text[1..];
+            if let Some(rest) = text.trim_start().strip_prefix(close) {
+                *text = rest;
+                return Some(());
+            }
+            loop {
+                item(text)?;
+                *text = text.trim_start();
+                if let Some(rest) = text.strip_prefix(',') {
+                    *text = rest;
+                } else {
+                    *text = text.strip_prefix(close)?;
+                    return Some(());
+                }
+            }
+        }
+
+        /// String without escapes, the profiler writes none
+        ///
+        fn string(text: &mut &str) -> Option<String> {
+            let rest = text.strip_prefix('"')?;
+            let end = rest.find(['"', '\\'])?;
+            if !rest[end..].starts_with('"') {
+                return None;
+            }
+            *text = &rest[end + 1..];
+            Some(rest[..end].to_string())
+        }
+
+        fn field(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => &fields.iter().find(|(k, _)| k == key).expect(key).1,
+                _ => panic!("not an object"),
+            }
+        }
+
+        fn array(&self) -> &[Json] {
+            match self {
+                Json::Array(items) => items,
+                _ => panic!("not an array"),
+            }
+        }
+    }
+}
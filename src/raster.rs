@@ -0,0 +1,697 @@
+//! Triangle rasterization with interpolation of
+//! per-vertex attributes (depth, color, UV, normals, ...)
+//!
+
+use crate::draw_3d::Vec3D;
+use std::cmp;
+
+/// Value which can be interpolated across triangle surface
+///
+/// Implemented for `f32`, `Vec3D`, `[f32; N]` and tuples of
+/// varyings, so custom attribute sets can be put together
+/// without implementing the trait by hand
+///
+pub trait Varying: Copy {
+    fn scale(&self, k: f32) -> Self;
+
+    fn sum(&self, other: &Self) -> Self;
+
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        self.scale(1.0 - t).sum(&other.scale(t))
+    }
+}
+
+impl Varying for () {
+    fn scale(&self, _k: f32) {}
+
+    fn sum(&self, _other: &()) {}
+}
+
+impl Varying for f32 {
+    fn scale(&self, k: f32) -> f32 {
+        self * k
+    }
+
+    fn sum(&self, other: &f32) -> f32 {
+        self + other
+    }
+}
+
+impl Varying for Vec3D {
+    fn scale(&self, k: f32) -> Vec3D {
+        *self * k
+    }
+
+    fn sum(&self, other: &Vec3D) -> Vec3D {
+        *self + *other
+    }
+}
+
+impl<const N: usize> Varying for [f32; N] {
+    fn scale(&self, k: f32) -> [f32; N] {
+        let mut r = *self;
+        for v in r.iter_mut() {
+            *v *= k;
+        }
+        r
+    }
+
+    fn sum(&self, other: &[f32; N]) -> [f32; N] {
+        let mut r = *self;
+        for (v, o) in r.iter_mut().zip(other.iter()) {
+            *v += o;
+        }
+        r
+    }
+}
+
+impl<A: Varying, B: Varying> Varying for (A, B) {
+    fn scale(&self, k: f32) -> (A, B) {
+        (self.0.scale(k), self.1.scale(k))
+    }
+
+    fn sum(&self, other: &(A, B)) -> (A, B) {
+        (self.0.sum(&other.0), self.1.sum(&other.1))
+    }
+}
+
+impl<A: Varying, B: Varying, C: Varying> Varying for (A, B, C) {
+    fn scale(&self, k: f32) -> (A, B, C) {
+        (self.0.scale(k), self.1.scale(k), self.2.scale(k))
+    }
+
+    fn sum(&self, other: &(A, B, C)) -> (A, B, C) {
+        (
+            self.0.sum(&other.0),
+            self.1.sum(&other.1),
+            self.2.sum(&other.2),
+        )
+    }
+}
+
+/// Triangle vertex ready for rasterization
+///
+#[derive(Clone, Copy, Debug)]
+pub struct RasterVertex<V: Varying> {
+    /// x and y in pixels, z is depth after perspective divide
+    pub pos: Vec3D,
+    /// Reciprocal of clip space `w`, used for perspective correction.
+    /// Use `1.0` for orthographic projection or plain 2D triangles
+    pub inv_w: f32,
+    pub attrs: V,
+}
+
+impl<V: Varying> RasterVertex<V> {
+    pub fn new(pos: Vec3D, inv_w: f32, attrs: V) -> RasterVertex<V> {
+        RasterVertex { pos, inv_w, attrs }
+    }
+}
+
+/// Single pixel produced by the rasterizer
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Fragment<V: Varying> {
+    pub x: i32,
+    pub y: i32,
+    pub depth: f32,
+    /// Perspective correct interpolated attributes
+    pub attrs: V,
+}
+
+/// Rectangle of pixels rasterization is limited to,
+/// maximal coordinates are exclusive
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+impl Bounds {
+    pub fn new(min_x: i32, min_y: i32, max_x: i32, max_y: i32) -> Bounds {
+        Bounds {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }
+    }
+
+    /// Whole `width` x `height` screen
+    ///
+    pub fn screen(width: i32, height: i32) -> Bounds {
+        Bounds::new(0, 0, width, height)
+    }
+
+    /// Pixels possibly covered by the triangle, clipped to `self`.
+    /// Empty bounds have `min >= max`
+    ///
+    pub fn triangle<V: Varying>(&self, verts: &[RasterVertex<V>; 3]) -> Bounds {
+        let min = verts[0].pos.min(&verts[1].pos).min(&verts[2].pos);
+        let max = verts[0].pos.max(&verts[1].pos).max(&verts[2].pos);
+
+        // Added before the cast, which saturates for huge coordinates
+        Bounds::new(
+            cmp::max(self.min_x, min.x.floor() as i32),
+            cmp::max(self.min_y, min.y.floor() as i32),
+            cmp::min(self.max_x, (max.x.floor() + 1.0) as i32),
+            cmp::min(self.max_y, (max.y.floor() + 1.0) as i32),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_x >= self.max_x || self.min_y >= self.max_y
+    }
+}
+
+/// Point on a triangle edge, attributes are premultiplied
+/// by `inv_w` so they can be interpolated linearly in screen space
+///
+#[derive(Clone, Copy)]
+struct EdgePoint<V: Varying> {
+    x: f32,
+    y: f32,
+    z: f32,
+    inv_w: f32,
+    attrs: V,
+}
+
+impl<V: Varying> EdgePoint<V> {
+    fn from_vertex(v: &RasterVertex<V>) -> EdgePoint<V> {
+        EdgePoint {
+            x: v.pos.x,
+            y: v.pos.y,
+            z: v.pos.z,
+            inv_w: v.inv_w,
+            attrs: v.attrs.scale(v.inv_w),
+        }
+    }
+
+    fn lerp(&self, other: &EdgePoint<V>, t: f32) -> EdgePoint<V> {
+        EdgePoint {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+            inv_w: self.inv_w + (other.inv_w - self.inv_w) * t,
+            attrs: self.attrs.lerp(&other.attrs, t),
+        }
+    }
+}
+
+/// Scanline triangle rasterizer
+///
+/// Calls `fragment` for every pixel whose center lies inside
+/// the triangle and inside `bounds`
+///
+pub fn triangle<V, F>(verts: &[RasterVertex<V>; 3], bounds: Bounds, mut fragment: F)
+where
+    V: Varying,
+    F: FnMut(Fragment<V>),
+{
+    let mut ps = [
+        EdgePoint::from_vertex(&verts[0]),
+        EdgePoint::from_vertex(&verts[1]),
+        EdgePoint::from_vertex(&verts[2]),
+    ];
+
+    ps.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap_or(cmp::Ordering::Equal));
+
+    let [top, mid, bottom] = ps;
+
+    if bottom.y - top.y <= 0.0 {
+        return;
+    }
+
+    // Pixel centers are at +0.5, covered rows satisfy top.y <= y + 0.5 < bottom.y
+    let y_start = cmp::max(bounds.min_y, (top.y - 0.5).ceil() as i32);
+    let y_end = cmp::min(bounds.max_y, (bottom.y - 0.5).ceil() as i32);
+
+    for y in y_start..y_end {
+        let sy = y as f32 + 0.5;
+        let long = top.lerp(&bottom, (sy - top.y) / (bottom.y - top.y));
+        let short = if sy < mid.y {
+            top.lerp(&mid, (sy - top.y) / (mid.y - top.y))
+        } else {
+            mid.lerp(&bottom, (sy - mid.y) / (bottom.y - mid.y))
+        };
+        let (left, right) = if long.x < short.x {
+            (long, short)
+        } else {
+            (short, long)
+        };
+
+        if right.x - left.x <= 0.0 {
+            continue;
+        }
+
+        let x_start = cmp::max(bounds.min_x, (left.x - 0.5).ceil() as i32);
+        let x_end = cmp::min(bounds.max_x, (right.x - 0.5).ceil() as i32);
+
+        for x in x_start..x_end {
+            let p = left.lerp(&right, (x as f32 + 0.5 - left.x) / (right.x - left.x));
+
+            fragment(Fragment {
+                x,
+                y,
+                depth: p.z,
+                attrs: p.attrs.scale(1.0 / p.inv_w),
+            });
+        }
+    }
+}
+
+/// Bits of sub-pixel precision used by `triangle_edge`
+///
+const SUBPIXEL_BITS: i32 = 4;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+
+/// Triangles with a vertex further than this many pixels from
+/// the origin are skipped by `triangle_edge`, so fixed-point edge
+/// functions can not overflow. There is no clipping to pull them in
+///
+pub const GUARD_BAND: f32 = (1 << 20) as f32;
+
+/// Triangle rasterization algorithm
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rasterizer {
+    /// Walks triangle edges row by row, see `triangle`
+    Scanline,
+    /// Tests pixels against edge functions, see `triangle_edge`
+    EdgeFunction,
+}
+
+impl Rasterizer {
+    pub fn toggle(self) -> Rasterizer {
+        match self {
+            Rasterizer::Scanline => Rasterizer::EdgeFunction,
+            Rasterizer::EdgeFunction => Rasterizer::Scanline,
+        }
+    }
+}
+
+/// Rasterizes triangle with selected algorithm
+///
+pub fn rasterize<V, F>(
+    rasterizer: Rasterizer,
+    verts: &[RasterVertex<V>; 3],
+    bounds: Bounds,
+    fragment: F,
+) where
+    V: Varying,
+    F: FnMut(Fragment<V>),
+{
+    match rasterizer {
+        Rasterizer::Scanline => triangle(verts, bounds, fragment),
+        Rasterizer::EdgeFunction => triangle_edge(verts, bounds, fragment),
+    }
+}
+
+/// Screen space line rasterizer (DDA)
+///
+/// Calls `fragment` with pixel position and depth, interpolated
+/// linearly between `a` and `b`, for every pixel of the line
+/// inside `bounds`. The line is clipped to `bounds` first
+///
+pub fn line<F>(a: Vec3D, b: Vec3D, bounds: Bounds, mut fragment: F)
+where
+    F: FnMut(i32, i32, f32),
+{
+    if bounds.is_empty() {
+        return;
+    }
+    let d = b - a;
+
+    // Liang-Barsky clipping against bounds
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for &(p, q) in &[
+        (-d.x, a.x - bounds.min_x as f32),
+        (d.x, bounds.max_x as f32 - a.x),
+        (-d.y, a.y - bounds.min_y as f32),
+        (d.y, bounds.max_y as f32 - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+        if t0 > t1 {
+            return;
+        }
+    }
+
+    let (start, end) = (a.lerp(&b, t0), a.lerp(&b, t1));
+    let steps = (end.x - start.x).abs().max((end.y - start.y).abs()).ceil() as i32;
+    for i in 0..=steps {
+        let p = start.lerp(&end, i as f32 / steps.max(1) as f32);
+        let (x, y) = (p.x.floor() as i32, p.y.floor() as i32);
+        // Clipped end can land exactly on the exclusive maximum
+        if x >= bounds.min_x && x < bounds.max_x && y >= bounds.min_y && y < bounds.max_y {
+            fragment(x, y, p.z);
+        }
+    }
+}
+
+/// Edge function of edge `a` -> `b` evaluated at point `p`
+///
+/// Positive on the inner side of edges of triangle with positive area
+///
+fn edge_function(a: (i64, i64), b: (i64, i64), p: (i64, i64)) -> i64 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+/// Top-left fill rule, sample points lying exactly on an edge
+/// belong to the triangle only if the edge is top or left one
+///
+/// Returns bias added to the edge function, so that `>= 0`
+/// test implements the rule
+///
+fn fill_bias(a: (i64, i64), b: (i64, i64)) -> i64 {
+    let dx = b.0 - a.0;
+    let dy = b.1 - a.1;
+
+    if (dy == 0 && dx > 0) || dy < 0 {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Maximal number of samples per pixel for `triangle_multisample`
+///
+pub const MAX_SAMPLES: usize = 8;
+
+/// Sample positions inside a pixel in 1/16 of a pixel,
+/// rotated grid patterns for 2, 4 and 8 samples
+///
+/// Panics for unsupported sample counts
+///
+pub fn sample_pattern(samples: usize) -> &'static [(i64, i64)] {
+    match samples {
+        1 => &[(8, 8)],
+        2 => &[(4, 4), (12, 12)],
+        4 => &[(6, 2), (14, 6), (2, 10), (10, 14)],
+        8 => &[
+            (9, 5),
+            (7, 11),
+            (13, 9),
+            (5, 3),
+            (3, 13),
+            (1, 7),
+            (11, 15),
+            (15, 1),
+        ],
+        _ => panic!("unsupported sample count {}, use 1, 2, 4 or 8", samples),
+    }
+}
+
+/// Coverage of a pixel by triangle
+///
+#[derive(Clone, Copy, Debug)]
+pub struct SampleCoverage {
+    /// Bit `i` is set if sample `i` of the pattern is inside triangle
+    pub mask: u32,
+    /// Depth of the triangle at each covered sample
+    pub depths: [f32; MAX_SAMPLES],
+}
+
+/// Half-space (edge function) triangle rasterizer
+///
+/// Vertex positions are snapped to 1/16 of a pixel and tested with
+/// exact integer arithmetic using the top-left fill rule, so triangles
+/// sharing an edge never leave gaps or cover the same pixel twice
+///
+/// Fragments do not depend on `bounds`, so triangle rasterized in
+/// parts (e.g. per screen tile) produces exactly the same pixels.
+/// Triangles reaching outside of `GUARD_BAND` are skipped
+///
+pub fn triangle_edge<V, F>(verts: &[RasterVertex<V>; 3], bounds: Bounds, mut fragment: F)
+where
+    V: Varying,
+    F: FnMut(Fragment<V>),
+{
+    triangle_samples(verts, bounds, sample_pattern(1), |frag, _| fragment(frag));
+}
+
+/// Multisampling variant of `triangle_edge`
+///
+/// Coverage and depth are evaluated for each of `samples` sample
+/// positions (see `sample_pattern`), attributes once at pixel center.
+/// Pixels are reported when at least one sample is covered
+///
+pub fn triangle_multisample<V, F>(
+    verts: &[RasterVertex<V>; 3],
+    bounds: Bounds,
+    samples: usize,
+    fragment: F,
+) where
+    V: Varying,
+    F: FnMut(Fragment<V>, &SampleCoverage),
+{
+    triangle_samples(verts, bounds, sample_pattern(samples), fragment);
+}
+
+fn triangle_samples<V, F>(
+    verts: &[RasterVertex<V>; 3],
+    bounds: Bounds,
+    pattern: &[(i64, i64)],
+    mut fragment: F,
+) where
+    V: Varying,
+    F: FnMut(Fragment<V>, &SampleCoverage),
+{
+    // Also rejects NaN positions
+    let in_guard_band =
+        |v: &RasterVertex<V>| v.pos.x.abs() <= GUARD_BAND && v.pos.y.abs() <= GUARD_BAND;
+    if !verts.iter().all(in_guard_band) {
+        return;
+    }
+
+    let to_fixed = |v: &RasterVertex<V>| {
+        (
+            (v.pos.x * SUBPIXEL_ONE as f32).round() as i64,
+            (v.pos.y * SUBPIXEL_ONE as f32).round() as i64,
+        )
+    };
+
+    let mut vs = [verts[0], verts[1], verts[2]];
+    let mut p = [to_fixed(&vs[0]), to_fixed(&vs[1]), to_fixed(&vs[2])];
+    let mut area = edge_function(p[0], p[1], p[2]);
+
+    if area == 0 {
+        return;
+    }
+    // Make winding consistent so inside is always positive
+    if area < 0 {
+        vs.swap(1, 2);
+        p.swap(1, 2);
+        area = -area;
+    }
+
+    let min_x = cmp::max(
+        bounds.min_x,
+        (p[0].0.min(p[1].0).min(p[2].0) >> SUBPIXEL_BITS) as i32,
+    );
+    let min_y = cmp::max(
+        bounds.min_y,
+        (p[0].1.min(p[1].1).min(p[2].1) >> SUBPIXEL_BITS) as i32,
+    );
+    let max_x = cmp::min(
+        bounds.max_x - 1,
+        (p[0].0.max(p[1].0).max(p[2].0) >> SUBPIXEL_BITS) as i32,
+    );
+    let max_y = cmp::min(
+        bounds.max_y - 1,
+        (p[0].1.max(p[1].1).max(p[2].1) >> SUBPIXEL_BITS) as i32,
+    );
+
+    if min_x > max_x || min_y > max_y {
+        return;
+    }
+
+    // Edge opposite to vertex i is edges[i]
+    let edges = [(p[1], p[2]), (p[2], p[0]), (p[0], p[1])];
+    let bias = [
+        fill_bias(edges[0].0, edges[0].1),
+        fill_bias(edges[1].0, edges[1].1),
+        fill_bias(edges[2].0, edges[2].1),
+    ];
+    // Values at the center of the first pixel and steps per pixel
+    let start = (
+        ((min_x as i64) << SUBPIXEL_BITS) + SUBPIXEL_ONE / 2,
+        ((min_y as i64) << SUBPIXEL_BITS) + SUBPIXEL_ONE / 2,
+    );
+    let mut row = [0i64; 3];
+    let mut step_x = [0i64; 3];
+    let mut step_y = [0i64; 3];
+    for i in 0..3 {
+        let (a, b) = edges[i];
+        row[i] = edge_function(a, b, start);
+        step_x[i] = -(b.1 - a.1) * SUBPIXEL_ONE;
+        step_y[i] = (b.0 - a.0) * SUBPIXEL_ONE;
+    }
+    // Edge function offsets of samples from pixel center
+    let sample_offsets: Vec<[i64; 3]> = pattern
+        .iter()
+        .map(|&(sx, sy)| {
+            let (dx, dy) = (sx - SUBPIXEL_ONE / 2, sy - SUBPIXEL_ONE / 2);
+            let mut o = [0i64; 3];
+            for i in 0..3 {
+                let (a, b) = edges[i];
+                o[i] = -(b.1 - a.1) * dx + (b.0 - a.0) * dy;
+            }
+            o
+        })
+        .collect();
+
+    let inv_area = 1.0 / area as f32;
+    let attrs = [
+        vs[0].attrs.scale(vs[0].inv_w),
+        vs[1].attrs.scale(vs[1].inv_w),
+        vs[2].attrs.scale(vs[2].inv_w),
+    ];
+    let depth_at = |e: &[i64; 3]| {
+        (e[0] as f32 * vs[0].pos.z + e[1] as f32 * vs[1].pos.z + e[2] as f32 * vs[2].pos.z)
+            * inv_area
+    };
+    let mut coverage = SampleCoverage {
+        mask: 0,
+        depths: [0.0; MAX_SAMPLES],
+    };
+
+    for y in min_y..=max_y {
+        let mut e = row;
+        for x in min_x..=max_x {
+            coverage.mask = 0;
+            for (s, o) in sample_offsets.iter().enumerate() {
+                let es = [e[0] + o[0], e[1] + o[1], e[2] + o[2]];
+                if es[0] + bias[0] >= 0 && es[1] + bias[1] >= 0 && es[2] + bias[2] >= 0 {
+                    coverage.mask |= 1 << s;
+                    coverage.depths[s] = depth_at(&es);
+                }
+            }
+
+            if coverage.mask != 0 {
+                // Barycentric coordinates at pixel center, may lie
+                // slightly outside of triangle for partially covered pixels
+                let b0 = e[0] as f32 * inv_area;
+                let b1 = e[1] as f32 * inv_area;
+                let b2 = e[2] as f32 * inv_area;
+                let inv_w = b0 * vs[0].inv_w + b1 * vs[1].inv_w + b2 * vs[2].inv_w;
+
+                fragment(
+                    Fragment {
+                        x,
+                        y,
+                        depth: depth_at(&e),
+                        attrs: attrs[0]
+                            .scale(b0)
+                            .sum(&attrs[1].scale(b1))
+                            .sum(&attrs[2].scale(b2))
+                            .scale(1.0 / inv_w),
+                    },
+                    &coverage,
+                );
+            }
+            for i in 0..3 {
+                e[i] += step_x[i];
+            }
+        }
+        for i in 0..3 {
+            row[i] += step_y[i];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32) -> RasterVertex<()> {
+        RasterVertex::new(Vec3D::new(x, y, 0.5), 1.0, ())
+    }
+
+    /// Fan of triangles around an off-center point, with vertices
+    /// at uneven angles so edges have all kinds of slopes
+    ///
+    fn fan() -> (Vec3D, f32, Vec<[RasterVertex<()>; 3]>) {
+        let center = Vec3D::new(32.3, 31.7, 0.5);
+        let radius = 25.0;
+        let angles: Vec<f32> = (0..13)
+            .map(|i| i as f32 * 0.48 + (i * i) as f32 * 0.003)
+            .collect();
+        let rim: Vec<RasterVertex<()>> = angles
+            .iter()
+            .map(|a| vertex(center.x + radius * a.cos(), center.y + radius * a.sin()))
+            .collect();
+        let c = RasterVertex::new(center, 1.0, ());
+        let tris = (0..rim.len())
+            .map(|i| [c, rim[i], rim[(i + 1) % rim.len()]])
+            .collect();
+        (center, radius, tris)
+    }
+
+    #[test]
+    fn shared_edges_watertight() {
+        let (center, radius, tris) = fan();
+        let bounds = Bounds::screen(64, 64);
+        let mut counts = vec![0; 64 * 64];
+        for verts in &tris {
+            triangle_edge(verts, bounds, |frag| {
+                counts[(frag.y * 64 + frag.x) as usize] += 1
+            });
+        }
+
+        // Largest gap between rim vertices is under 0.6 rad,
+        // so the fan covers circle of radius * cos(0.3)
+        let inner = radius * 0.3f32.cos() - 1.0;
+        for y in 0..64 {
+            for x in 0..64 {
+                let count = counts[(y * 64 + x) as usize];
+                assert!(count <= 1, "pixel {} {} covered {} times", x, y, count);
+                let (dx, dy) = (x as f32 + 0.5 - center.x, y as f32 + 0.5 - center.y);
+                if (dx * dx + dy * dy).sqrt() < inner {
+                    assert_eq!(count, 1, "pixel {} {} not covered", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shared_edges_watertight_multisample() {
+        let (_, _, tris) = fan();
+        let bounds = Bounds::screen(64, 64);
+        let mut masks = vec![0u32; 64 * 64];
+        for verts in &tris {
+            triangle_multisample(verts, bounds, 4, |frag, coverage| {
+                let mask = &mut masks[(frag.y * 64 + frag.x) as usize];
+                assert_eq!(*mask & coverage.mask, 0, "sample covered twice");
+                *mask |= coverage.mask;
+            });
+        }
+        assert!(masks.contains(&0b1111));
+    }
+
+    #[test]
+    fn huge_coordinates_skipped() {
+        let bounds = Bounds::screen(64, 32);
+        for &far in &[3e8, -3e9, f32::INFINITY, f32::NAN] {
+            let verts = [vertex(10.0, 10.0), vertex(far, 20.0), vertex(10.0, 30.0)];
+            triangle_edge(&verts, bounds, |_| panic!("fragment of {}", far));
+            triangle_multisample(&verts, bounds, 4, |_, _| panic!("fragment of {}", far));
+        }
+    }
+
+    #[test]
+    fn triangle_bounds_far_off_screen() {
+        let screen = Bounds::screen(64, 32);
+        let verts = [vertex(10.0, 10.0), vertex(3e9, 20.0), vertex(10.0, -3e9)];
+        assert_eq!(screen.triangle(&verts), Bounds::new(10, 0, 64, 21));
+    }
+}
@@ -0,0 +1,206 @@
+//! Programmable shading stages of the software pipeline
+//!
+//! Vertex shader runs once per triangle vertex and produces clip space
+//! position plus varyings, fragment shader runs once per covered pixel
+//! with varyings interpolated by the rasterizer
+//!
+
+use crate::draw_3d::{Material, Vec3D, Vec4D};
+use crate::raster::{Fragment, Varying};
+use crate::transform;
+use sdl2::pixels::Color;
+
+/// Per-vertex input of the vertex shader
+///
+#[derive(Clone, Copy, Debug)]
+pub struct VertexInput {
+    /// Object space position
+    pub pos: Vec3D,
+    /// Object space face normal of the triangle
+    pub normal: Vec3D,
+    pub material: Material,
+}
+
+/// Vertex stage
+///
+/// `Uniforms` are values constant for the whole draw call,
+/// `Varyings` are interpolated across triangle and passed
+/// to the fragment shader
+///
+pub trait VertexShader {
+    type Uniforms;
+    type Varyings: Varying;
+
+    /// Returns clip space position and varyings of the vertex
+    ///
+    fn vertex(&self, uniforms: &Self::Uniforms, input: &VertexInput) -> (Vec4D, Self::Varyings);
+
+    /// Object space -> clip space matrix, if the shader transforms
+    /// positions by one. Used for culling whole meshes
+    ///
+    fn clip_matrix(&self, _uniforms: &Self::Uniforms) -> Option<transform::Mat4x4> {
+        None
+    }
+
+    /// Varyings of the vertex whose clip space position the pipeline
+    /// already computed with `clip_matrix`, in batches using SIMD
+    ///
+    /// Default runs `vertex` and drops its position, shaders
+    /// providing `clip_matrix` should skip the transform
+    ///
+    fn varyings(&self, uniforms: &Self::Uniforms, input: &VertexInput) -> Self::Varyings {
+        self.vertex(uniforms, input).1
+    }
+}
+
+/// Fragment stage
+///
+pub trait FragmentShader<U, V: Varying> {
+    /// Returns color of the pixel, `None` discards the pixel
+    ///
+    fn fragment(&self, uniforms: &U, frag: &Fragment<V>) -> Option<Color>;
+}
+
+/// Uniforms used by built-in shaders
+///
+/// Create with `new`, which precomposes the matrices. Changing
+/// them afterwards requires updating `world_view_proj` and `normal` too
+///
+pub struct SceneUniforms {
+    pub world: transform::Mat4x4,
+    pub view: transform::Mat4x4,
+    pub proj: transform::Mat4x4,
+    /// world * view * proj
+    pub world_view_proj: transform::Mat4x4,
+    /// Inverse transpose of `world`, keeps normals perpendicular
+    /// to surfaces under non-uniform scale
+    pub normal: transform::Mat4x4,
+    /// Direction light is travelling in, normalized
+    pub light: Vec3D,
+}
+
+impl SceneUniforms {
+    pub fn new(
+        world: transform::Mat4x4,
+        view: transform::Mat4x4,
+        proj: transform::Mat4x4,
+        light: Vec3D,
+    ) -> SceneUniforms {
+        SceneUniforms {
+            world,
+            view,
+            proj,
+            world_view_proj: world * view * proj,
+            // Singular world matrix flattens the mesh, its normals do not matter
+            normal: world.inverse().map_or(world, |inverse| inverse.transpose()),
+            light,
+        }
+    }
+
+    /// Object space -> clip space
+    ///
+    pub fn to_clip(&self, pos: &Vec3D) -> Vec4D {
+        transform::mult_matrix_vec4(&Vec4D::from_point(pos), &self.world_view_proj)
+    }
+
+    /// Object space -> world space normal
+    ///
+    pub fn to_world_normal(&self, normal: &Vec3D) -> Vec3D {
+        transform::mult_matrix_direction(normal, &self.normal).normalize()
+    }
+}
+
+/// Flat shading of material color from a single directional light
+///
+pub struct FlatShader;
+
+impl VertexShader for FlatShader {
+    type Uniforms = SceneUniforms;
+    /// Light intensity and diffuse color
+    type Varyings = (f32, Vec3D);
+
+    fn vertex(&self, uniforms: &SceneUniforms, input: &VertexInput) -> (Vec4D, (f32, Vec3D)) {
+        (uniforms.to_clip(&input.pos), self.varyings(uniforms, input))
+    }
+
+    fn clip_matrix(&self, uniforms: &SceneUniforms) -> Option<transform::Mat4x4> {
+        Some(uniforms.world_view_proj)
+    }
+
+    fn varyings(&self, uniforms: &SceneUniforms, input: &VertexInput) -> (f32, Vec3D) {
+        let normal = uniforms.to_world_normal(&input.normal);
+        let color = input.material.color;
+
+        (
+            normal.dot_product(&uniforms.light),
+            Vec3D::new(color.r as f32, color.g as f32, color.b as f32) / 255.0,
+        )
+    }
+}
+
+impl FragmentShader<SceneUniforms, (f32, Vec3D)> for FlatShader {
+    fn fragment(&self, _uniforms: &SceneUniforms, frag: &Fragment<(f32, Vec3D)>) -> Option<Color> {
+        let (intensity, diffuse) = frag.attrs;
+        let mut shade = (255.0 * intensity) as u8;
+        // Small hack for avoid pitch black shades
+        if shade == 0 {
+            shade = 10;
+        }
+        let c = diffuse * shade as f32;
+        Some(Color::RGB(
+            c.x.round() as u8,
+            c.y.round() as u8,
+            c.z.round() as u8,
+        ))
+    }
+}
+
+/// Visualizes world space normals as colors
+///
+pub struct NormalShader;
+
+impl VertexShader for NormalShader {
+    type Uniforms = SceneUniforms;
+    type Varyings = Vec3D;
+
+    fn vertex(&self, uniforms: &SceneUniforms, input: &VertexInput) -> (Vec4D, Vec3D) {
+        (uniforms.to_clip(&input.pos), self.varyings(uniforms, input))
+    }
+
+    fn clip_matrix(&self, uniforms: &SceneUniforms) -> Option<transform::Mat4x4> {
+        Some(uniforms.world_view_proj)
+    }
+
+    fn varyings(&self, uniforms: &SceneUniforms, input: &VertexInput) -> Vec3D {
+        uniforms.to_world_normal(&input.normal)
+    }
+}
+
+impl FragmentShader<SceneUniforms, Vec3D> for NormalShader {
+    fn fragment(&self, _uniforms: &SceneUniforms, frag: &Fragment<Vec3D>) -> Option<Color> {
+        let n = (frag.attrs.normalize() + Vec3D::new(1.0, 1.0, 1.0)) * 127.5;
+
+        Some(Color::RGB(n.x as u8, n.y as u8, n.z as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Mat4x4;
+
+    #[test]
+    fn normals_under_non_uniform_scale() {
+        let world = Mat4x4::mat_scale(1.0, 4.0, 0.5) * Mat4x4::mat_rot_z(&0.3);
+        let uniforms = SceneUniforms::new(world, Mat4x4::id(), Mat4x4::id(), Vec3D::default());
+
+        // Slanted plane, its normal has to stay perpendicular to its tangents
+        let (a, b) = (Vec3D::new(1.0, -1.0, 0.0), Vec3D::new(0.0, 1.0, 2.0));
+        let normal = uniforms.to_world_normal(&a.cross_product(&b));
+        for tangent in &[a, b] {
+            let tangent = transform::mult_matrix_direction(tangent, &world).normalize();
+            assert!(normal.dot_product(&tangent).abs() < 1e-5);
+        }
+        assert!((normal.len() - 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,233 @@
+//! SIMD versions of matrix operations
+//!
+//! Implementation is selected at runtime by CPU feature detection,
+//! CPUs without SSE and other architectures use scalar code. All
+//! paths multiply and add in the same order as `transform` does,
+//! so results are exactly the same as the scalar ones
+//!
+
+use crate::draw_3d::{Vec3D, Vec4D};
+use crate::transform::{self, Mat4x4};
+use std::sync::OnceLock;
+
+/// Instruction set used for matrix operations
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Scalar,
+    Sse,
+    /// Transforms two points per instruction, matrix
+    /// products are the same as with SSE
+    Avx,
+}
+
+impl Backend {
+    /// Best backend supported by the CPU, detected on the first call
+    ///
+    pub fn detect() -> Backend {
+        static BEST: OnceLock<Backend> = OnceLock::new();
+        *BEST.get_or_init(|| {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            {
+                if is_x86_feature_detected!("avx") {
+                    return Backend::Avx;
+                }
+                if is_x86_feature_detected!("sse") {
+                    return Backend::Sse;
+                }
+            }
+            Backend::Scalar
+        })
+    }
+
+    /// Backends which can run on the CPU, `Scalar` first
+    ///
+    pub fn supported() -> Vec<Backend> {
+        let best = Backend::detect();
+        [Backend::Scalar, Backend::Sse, Backend::Avx]
+            .iter()
+            .copied()
+            .filter(|&backend| backend as u8 <= best as u8)
+            .collect()
+    }
+}
+
+/// Matrix product `a * b`
+///
+/// Panics if `backend` is not supported by the CPU
+///
+pub fn mat_mul(backend: Backend, a: &Mat4x4, b: &Mat4x4) -> Mat4x4 {
+    check(backend);
+    match backend {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Backend::Sse | Backend::Avx => unsafe { x86::mat_mul(a, b) },
+        _ => a.mul_scalar(b),
+    }
+}
+
+/// Multiplies points (fourth value is implied 1) over the matrix
+///
+/// Panics if `backend` is not supported by the CPU
+///
+pub fn transform_points(backend: Backend, points: &[Vec3D], m: &Mat4x4) -> Vec<Vec4D> {
+    check(backend);
+    let mut out = Vec::with_capacity(points.len());
+    match backend {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Backend::Sse => unsafe { x86::transform_points(points, m, &mut out) },
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Backend::Avx => unsafe { x86::transform_points_avx(points, m, &mut out) },
+        _ => out.extend(
+            points
+                .iter()
+                .map(|p| transform::mult_matrix_vec4(&Vec4D::from_point(p), m)),
+        ),
+    }
+    out
+}
+
+/// SIMD code must not run on CPUs without the instructions,
+/// costs a single load as `Backend::detect` is cached
+///
+fn check(backend: Backend) {
+    assert!(
+        backend as u8 <= Backend::detect() as u8,
+        "{:?} is not supported by this CPU",
+        backend
+    );
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    use crate::draw_3d::{Vec3D, Vec4D};
+    use crate::transform::Mat4x4;
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse")]
+    unsafe fn rows(m: &Mat4x4) -> [__m128; 4] {
+        [
+            _mm_loadu_ps(m.m[0].as_ptr()),
+            _mm_loadu_ps(m.m[1].as_ptr()),
+            _mm_loadu_ps(m.m[2].as_ptr()),
+            _mm_loadu_ps(m.m[3].as_ptr()),
+        ]
+    }
+
+    /// Row vector `v` times matrix given by its rows
+    ///
+    #[target_feature(enable = "sse")]
+    unsafe fn row_times(v: [f32; 4], rows: &[__m128; 4]) -> __m128 {
+        let mut acc = _mm_mul_ps(_mm_set1_ps(v[0]), rows[0]);
+        acc = _mm_add_ps(acc, _mm_mul_ps(_mm_set1_ps(v[1]), rows[1]));
+        acc = _mm_add_ps(acc, _mm_mul_ps(_mm_set1_ps(v[2]), rows[2]));
+        _mm_add_ps(acc, _mm_mul_ps(_mm_set1_ps(v[3]), rows[3]))
+    }
+
+    fn to_vec4(v: [f32; 4]) -> Vec4D {
+        Vec4D::new(v[0], v[1], v[2], v[3])
+    }
+
+    #[target_feature(enable = "sse")]
+    pub unsafe fn mat_mul(a: &Mat4x4, b: &Mat4x4) -> Mat4x4 {
+        let rows = rows(b);
+        let mut m = Mat4x4::new_empty();
+        for r in 0..4 {
+            _mm_storeu_ps(m.m[r].as_mut_ptr(), row_times(a.m[r], &rows));
+        }
+        m
+    }
+
+    #[target_feature(enable = "sse")]
+    pub unsafe fn transform_points(points: &[Vec3D], m: &Mat4x4, out: &mut Vec<Vec4D>) {
+        let rows = rows(m);
+        let mut v = [0.0f32; 4];
+        for p in points {
+            _mm_storeu_ps(v.as_mut_ptr(), row_times([p.x, p.y, p.z, 1.0], &rows));
+            out.push(to_vec4(v));
+        }
+    }
+
+    /// Transforms pairs of points, one in each 128-bit half
+    ///
+    #[target_feature(enable = "avx")]
+    pub unsafe fn transform_points_avx(points: &[Vec3D], m: &Mat4x4, out: &mut Vec<Vec4D>) {
+        let half_rows = rows(m);
+        let rows = [
+            _mm256_broadcast_ps(&half_rows[0]),
+            _mm256_broadcast_ps(&half_rows[1]),
+            _mm256_broadcast_ps(&half_rows[2]),
+            _mm256_broadcast_ps(&half_rows[3]),
+        ];
+        let pair = |a: f32, b: f32| _mm256_set_m128(_mm_set1_ps(b), _mm_set1_ps(a));
+        let mut v = [0.0f32; 8];
+
+        let mut chunks = points.chunks_exact(2);
+        for p in &mut chunks {
+            let mut acc = _mm256_mul_ps(pair(p[0].x, p[1].x), rows[0]);
+            acc = _mm256_add_ps(acc, _mm256_mul_ps(pair(p[0].y, p[1].y), rows[1]));
+            acc = _mm256_add_ps(acc, _mm256_mul_ps(pair(p[0].z, p[1].z), rows[2]));
+            acc = _mm256_add_ps(acc, _mm256_mul_ps(pair(1.0, 1.0), rows[3]));
+            _mm256_storeu_ps(v.as_mut_ptr(), acc);
+            out.push(to_vec4([v[0], v[1], v[2], v[3]]));
+            out.push(to_vec4([v[4], v[5], v[6], v[7]]));
+        }
+        transform_points(chunks.remainder(), m, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Odd count, so the AVX path also transforms a single remaining point
+    ///
+    fn points() -> Vec<Vec3D> {
+        (0..101)
+            .map(|i| {
+                let i = i as f32;
+                Vec3D::new(i * 0.37 - 12.0, (i * 1.3).sin() * 40.0, 1.0 / (i + 1.0))
+            })
+            .collect()
+    }
+
+    fn matrices() -> (Mat4x4, Mat4x4) {
+        let a = Mat4x4::mat_rot_axis(&Vec3D::new(1.0, 1.0, 0.0), &0.3);
+        let b = Mat4x4::mat_trans(0.5, -0.2, 3.0) * Mat4x4::mat_proj(90.0, 0.5, 1000.0, 0.1);
+        (a, b)
+    }
+
+    #[test]
+    fn transform_points_matches_scalar() {
+        let points = points();
+        let (a, b) = matrices();
+        for m in &[a, b, a.mul_scalar(&b)] {
+            let scalar = transform_points(Backend::Scalar, &points, m);
+            for backend in Backend::supported() {
+                assert!(
+                    transform_points(backend, &points, m) == scalar,
+                    "{:?}",
+                    backend
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mat_mul_matches_scalar() {
+        let (a, b) = matrices();
+        let scalar = mat_mul(Backend::Scalar, &a, &b);
+        for backend in Backend::supported() {
+            // Exact, `PartialEq` of matrices has a tolerance
+            assert_eq!(mat_mul(backend, &a, &b).m, scalar.m, "{:?}", backend);
+            assert_eq!(
+                mat_mul(backend, &b, &a).m,
+                b.mul_scalar(&a).m,
+                "{:?}",
+                backend
+            );
+        }
+    }
+}
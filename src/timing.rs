@@ -0,0 +1,169 @@
+//! Frame pacing and fixed timestep updates
+//!
+//! Render rate is limited by `FramePacer`, animation advances
+//! in fixed steps counted by `FixedTimestep`, so it runs the same
+//! regardless of how fast frames are rendered
+//!
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Sleeping stops this long before the frame deadline and the rest
+/// is waited out by yielding, `thread::sleep` tends to oversleep
+///
+const SPIN_MARGIN: Duration = Duration::from_millis(1);
+
+/// Most fixed steps run per frame, slow frames would otherwise
+/// queue more and more steps
+///
+const MAX_STEPS: u32 = 8;
+
+/// Limits frame rate by sleeping the rest of every frame
+///
+pub struct FramePacer {
+    /// `None` renders frames as fast as possible
+    pub target_fps: Option<u32>,
+    frame_start: Instant,
+}
+
+impl FramePacer {
+    pub fn new(target_fps: Option<u32>) -> FramePacer {
+        FramePacer {
+            target_fps,
+            frame_start: Instant::now(),
+        }
+    }
+
+    /// Starts new frame, returns time since start of the previous one
+    ///
+    pub fn begin_frame(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.frame_start;
+        self.frame_start = now;
+        elapsed
+    }
+
+    /// Waits until the frame took `1 / target_fps` seconds,
+    /// returns immediately when uncapped or late
+    ///
+    pub fn end_frame(&self) {
+        let fps = match self.target_fps {
+            Some(fps) if fps > 0 => fps,
+            _ => return,
+        };
+        let deadline = self.frame_start + Duration::from_secs(1) / fps;
+
+        let now = Instant::now();
+        if deadline > now + SPIN_MARGIN {
+            thread::sleep(deadline - now - SPIN_MARGIN);
+        }
+        while Instant::now() < deadline {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Accumulates frame time and splits it into fixed update steps
+///
+pub struct FixedTimestep {
+    pub step: Duration,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    /// Timestep running `rate` updates per second
+    ///
+    pub fn new(rate: u32) -> FixedTimestep {
+        FixedTimestep {
+            step: Duration::from_secs(1) / rate.max(1),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// Adds frame time, returns number of steps to run now
+    ///
+    /// Time beyond `MAX_STEPS` steps is dropped, animation
+    /// then slows down instead of falling further behind
+    ///
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+            if steps == MAX_STEPS {
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+        }
+        steps
+    }
+
+    /// Step length in seconds
+    ///
+    pub fn dt(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// Fraction of the next step already elapsed, for interpolating
+    /// between the last two updated states
+    ///
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn step_count() {
+        let mut timestep = FixedTimestep::new(100);
+        assert_eq!(timestep.step, ms(10));
+        assert_eq!(timestep.dt(), 0.01);
+
+        assert_eq!(timestep.advance(ms(5)), 0);
+        assert_eq!(timestep.advance(ms(5)), 1);
+        assert_eq!(timestep.advance(ms(25)), 2);
+        // The 5 ms left over add to the next frame
+        assert_eq!(timestep.advance(ms(15)), 2);
+        assert_eq!(timestep.advance(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn zero_rate() {
+        let mut timestep = FixedTimestep::new(0);
+        assert_eq!(timestep.step, Duration::from_secs(1));
+        assert_eq!(timestep.advance(ms(2500)), 2);
+    }
+
+    #[test]
+    fn steps_clamped() {
+        let mut timestep = FixedTimestep::new(100);
+        assert_eq!(timestep.advance(ms(1000)), MAX_STEPS);
+        // Time beyond the clamp is dropped, not run on later frames
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(ms(5)), 0);
+
+        let mut timestep = FixedTimestep::new(100);
+        assert_eq!(timestep.advance(ms(10) * MAX_STEPS), MAX_STEPS);
+        assert_eq!(timestep.advance(ms(10) * (MAX_STEPS - 1)), MAX_STEPS - 1);
+    }
+
+    #[test]
+    fn alpha() {
+        let mut timestep = FixedTimestep::new(100);
+        assert_eq!(timestep.alpha(), 0.0);
+        timestep.advance(ms(4));
+        assert!((timestep.alpha() - 0.4).abs() < 1e-6);
+        timestep.advance(ms(8));
+        assert!((timestep.alpha() - 0.2).abs() < 1e-6);
+        timestep.advance(ms(8));
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+}
@@ -3,9 +3,37 @@
 //!
 
 use crate::draw_3d;
+use crate::simd;
+use std::ops::{Index, IndexMut, Mul};
+
+/// Tolerance used when comparing matrices
+///
+pub const EPSILON: f32 = 1e-5;
+
+/// Projection used for rendering the scene
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel projection without foreshortening, handy for engineering drawings
+    Orthographic,
+}
+
+impl Projection {
+    pub fn toggle(self) -> Projection {
+        match self {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Perspective,
+        }
+    }
+}
 
 /// 4x4 Matrix
 ///
+/// Vectors are treated as rows, so transformations
+/// are applied left to right: `v * a * b`
+///
+#[derive(Clone, Copy, Debug)]
 pub struct Mat4x4 {
     pub m: [[f32; 4]; 4],
 }
@@ -47,12 +75,58 @@ impl Mat4x4 {
 
     /// Helper method for generating projection matrix
     ///
+    /// `fov_deg` is vertical field of view in degrees,
+    /// `aspect_ratio` is screen height divided by screen width
+    ///
     pub fn mat_proj(fov_deg: f32, aspect_ratio: f32, far: f32, near: f32) -> Mat4x4 {
         let mut m = Mat4x4::new_empty();
-        let fov_rad: f32 = 1.0 / (fov_deg).tan();
+        let fov_scale: f32 = 1.0 / (fov_deg * 0.5).to_radians().tan();
+
+        m.m[0][0] = aspect_ratio * fov_scale;
+        m.m[1][1] = fov_scale;
+        m.m[2][2] = far / (far - near);
+        m.m[2][3] = 1.0;
+        m.m[3][2] = (-near * far) / (far - near);
+        m
+    }
+
+    /// Helper method for generating orthographic projection matrix
+    ///
+    /// Maps box given by `left`, `right`, `bottom`, `top`, `near` and `far`
+    /// to x, y in [-1, 1] and z in [0, 1] (same depth range as `mat_proj`)
+    ///
+    pub fn mat_ortho(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Mat4x4 {
+        let mut m = Mat4x4::new_empty();
+
+        m.m[0][0] = 2.0 / (right - left);
+        m.m[1][1] = 2.0 / (top - bottom);
+        m.m[2][2] = 1.0 / (far - near);
+        m.m[3][0] = -(right + left) / (right - left);
+        m.m[3][1] = -(top + bottom) / (top - bottom);
+        m.m[3][2] = -near / (far - near);
+        m.m[3][3] = 1.0;
+        m
+    }
+
+    /// Helper method for generating off-axis (asymmetric) projection matrix
+    ///
+    /// `left`, `right`, `bottom` and `top` are extents of the view window
+    /// on the near plane. Symmetric window gives the same matrix as `mat_proj`
+    ///
+    pub fn mat_frustum(
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    ) -> Mat4x4 {
+        let mut m = Mat4x4::new_empty();
 
-        m.m[0][0] = aspect_ratio * fov_rad;
-        m.m[1][1] = fov_rad;
+        m.m[0][0] = 2.0 * near / (right - left);
+        m.m[1][1] = 2.0 * near / (top - bottom);
+        m.m[2][0] = -(right + left) / (right - left);
+        m.m[2][1] = -(top + bottom) / (top - bottom);
         m.m[2][2] = far / (far - near);
         m.m[2][3] = 1.0;
         m.m[3][2] = (-near * far) / (far - near);
@@ -92,6 +166,39 @@ impl Mat4x4 {
         m
     }
 
+    /// Rotation about arbitrary `axis` by `rad` radians
+    ///
+    /// Axis does not have to be normalized. Rotating about x or z
+    /// axis matches `mat_rot_x` and `mat_rot_z`, rotating about y axis
+    /// matches `mat_rot_y` with negated angle
+    ///
+    pub fn mat_rot_axis(axis: &draw_3d::Vec3D, rad: &f32) -> Mat4x4 {
+        let k = axis.normalize();
+        let c = rad.cos();
+        let s = rad.sin();
+        let t = 1.0 - c;
+        let mut m = Mat4x4::id();
+
+        m.m[0][0] = t * k.x * k.x + c;
+        m.m[0][1] = t * k.x * k.y + s * k.z;
+        m.m[0][2] = t * k.x * k.z - s * k.y;
+        m.m[1][0] = t * k.x * k.y - s * k.z;
+        m.m[1][1] = t * k.y * k.y + c;
+        m.m[1][2] = t * k.y * k.z + s * k.x;
+        m.m[2][0] = t * k.x * k.z + s * k.y;
+        m.m[2][1] = t * k.y * k.z - s * k.x;
+        m.m[2][2] = t * k.z * k.z + c;
+        m
+    }
+
+    pub fn mat_scale(x: f32, y: f32, z: f32) -> Mat4x4 {
+        let mut m = Mat4x4::id();
+        m.m[0][0] = x;
+        m.m[1][1] = y;
+        m.m[2][2] = z;
+        m
+    }
+
     pub fn mat_trans(x: f32, y: f32, z: f32) -> Mat4x4 {
         let mut m = Mat4x4::id();
         m.m[3][0] = x;
@@ -101,15 +208,8 @@ impl Mat4x4 {
     }
 
     pub fn point_at(pos: &draw_3d::Vec3D, target: &draw_3d::Vec3D, up: &draw_3d::Vec3D) -> Mat4x4 {
-        let forward = target.sub(pos).normalize();
-        let up_forward_dp = up.dot_product(&forward);
-        let new_up = up
-            .sub(&forward.mul(&draw_3d::Vec3D::new(
-                up_forward_dp,
-                up_forward_dp,
-                up_forward_dp,
-            )))
-            .normalize();
+        let forward = (*target - *pos).normalize();
+        let new_up = (*up - forward * up.dot_product(&forward)).normalize();
         let right = new_up.cross_product(&forward);
         let mut m = Mat4x4::new_empty();
 
@@ -171,7 +271,15 @@ impl Mat4x4 {
         m
     }
 
+    /// Matrix product, uses SIMD when CPU supports it
+    ///
     pub fn mul(&self, other: &Mat4x4) -> Mat4x4 {
+        simd::mat_mul(simd::Backend::detect(), self, other)
+    }
+
+    /// Matrix product without SIMD, reference for `simd`
+    ///
+    pub fn mul_scalar(&self, other: &Mat4x4) -> Mat4x4 {
         let mut m = Mat4x4::new_empty();
 
         for c in 0..4 {
@@ -185,16 +293,584 @@ impl Mat4x4 {
 
         m
     }
+
+    pub fn transpose(&self) -> Mat4x4 {
+        let mut m = Mat4x4::new_empty();
+
+        for r in 0..4 {
+            for c in 0..4 {
+                m.m[r][c] = self.m[c][r];
+            }
+        }
+
+        m
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let (s, c) = self.sub_determinants();
+
+        s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1] + s[5] * c[0]
+    }
+
+    /// General inverse of the matrix
+    ///
+    /// Returns `None` for singular (non-invertible) matrices.
+    /// For camera matrices from `point_at` cheaper `to_look_at` can be used
+    ///
+    pub fn inverse(&self) -> Option<Mat4x4> {
+        let (s, c) = self.sub_determinants();
+        let det = s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1] + s[5] * c[0];
+
+        // Only exact zero is singular, scaled down scenes
+        // have tiny but valid determinants
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+
+        let a = &self.m;
+        let inv_det = 1.0 / det;
+        let mut m = Mat4x4::new_empty();
+
+        m.m[0][0] = (a[1][1] * c[5] - a[1][2] * c[4] + a[1][3] * c[3]) * inv_det;
+        m.m[0][1] = (-a[0][1] * c[5] + a[0][2] * c[4] - a[0][3] * c[3]) * inv_det;
+        m.m[0][2] = (a[3][1] * s[5] - a[3][2] * s[4] + a[3][3] * s[3]) * inv_det;
+        m.m[0][3] = (-a[2][1] * s[5] + a[2][2] * s[4] - a[2][3] * s[3]) * inv_det;
+
+        m.m[1][0] = (-a[1][0] * c[5] + a[1][2] * c[2] - a[1][3] * c[1]) * inv_det;
+        m.m[1][1] = (a[0][0] * c[5] - a[0][2] * c[2] + a[0][3] * c[1]) * inv_det;
+        m.m[1][2] = (-a[3][0] * s[5] + a[3][2] * s[2] - a[3][3] * s[1]) * inv_det;
+        m.m[1][3] = (a[2][0] * s[5] - a[2][2] * s[2] + a[2][3] * s[1]) * inv_det;
+
+        m.m[2][0] = (a[1][0] * c[4] - a[1][1] * c[2] + a[1][3] * c[0]) * inv_det;
+        m.m[2][1] = (-a[0][0] * c[4] + a[0][1] * c[2] - a[0][3] * c[0]) * inv_det;
+        m.m[2][2] = (a[3][0] * s[4] - a[3][1] * s[2] + a[3][3] * s[0]) * inv_det;
+        m.m[2][3] = (-a[2][0] * s[4] + a[2][1] * s[2] - a[2][3] * s[0]) * inv_det;
+
+        m.m[3][0] = (-a[1][0] * c[3] + a[1][1] * c[1] - a[1][2] * c[0]) * inv_det;
+        m.m[3][1] = (a[0][0] * c[3] - a[0][1] * c[1] + a[0][2] * c[0]) * inv_det;
+        m.m[3][2] = (-a[3][0] * s[3] + a[3][1] * s[1] - a[3][2] * s[0]) * inv_det;
+        m.m[3][3] = (a[2][0] * s[3] - a[2][1] * s[1] + a[2][2] * s[0]) * inv_det;
+
+        Some(m)
+    }
+
+    /// Compares matrices element by element with given tolerance
+    ///
+    pub fn approx_eq(&self, other: &Mat4x4, epsilon: f32) -> bool {
+        for r in 0..4 {
+            for c in 0..4 {
+                if (self.m[r][c] - other.m[r][c]).abs() > epsilon {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// 2x2 sub-determinants of top two rows (`s`) and
+    /// bottom two rows (`c`) shared by `determinant` and `inverse`
+    ///
+    fn sub_determinants(&self) -> ([f32; 6], [f32; 6]) {
+        let a = &self.m;
+        let s = [
+            a[0][0] * a[1][1] - a[1][0] * a[0][1],
+            a[0][0] * a[1][2] - a[1][0] * a[0][2],
+            a[0][0] * a[1][3] - a[1][0] * a[0][3],
+            a[0][1] * a[1][2] - a[1][1] * a[0][2],
+            a[0][1] * a[1][3] - a[1][1] * a[0][3],
+            a[0][2] * a[1][3] - a[1][2] * a[0][3],
+        ];
+        let c = [
+            a[2][0] * a[3][1] - a[3][0] * a[2][1],
+            a[2][0] * a[3][2] - a[3][0] * a[2][2],
+            a[2][0] * a[3][3] - a[3][0] * a[2][3],
+            a[2][1] * a[3][2] - a[3][1] * a[2][2],
+            a[2][1] * a[3][3] - a[3][1] * a[2][3],
+            a[2][2] * a[3][3] - a[3][2] * a[2][3],
+        ];
+        (s, c)
+    }
+}
+
+impl Mul for Mat4x4 {
+    type Output = Mat4x4;
+
+    fn mul(self, other: Mat4x4) -> Mat4x4 {
+        Mat4x4::mul(&self, &other)
+    }
+}
+
+impl<'a> Mul<&'a Mat4x4> for &'a Mat4x4 {
+    type Output = Mat4x4;
+
+    fn mul(self, other: &'a Mat4x4) -> Mat4x4 {
+        Mat4x4::mul(self, other)
+    }
+}
+
+/// Row access, `m[r][c]` is the same as `m.m[r][c]`
+///
+impl Index<usize> for Mat4x4 {
+    type Output = [f32; 4];
+
+    fn index(&self, row: usize) -> &[f32; 4] {
+        &self.m[row]
+    }
+}
+
+impl IndexMut<usize> for Mat4x4 {
+    fn index_mut(&mut self, row: usize) -> &mut [f32; 4] {
+        &mut self.m[row]
+    }
+}
+
+/// Matrices are equal when all elements differ by at most `EPSILON`
+///
+impl PartialEq for Mat4x4 {
+    fn eq(&self, other: &Mat4x4) -> bool {
+        self.approx_eq(other, EPSILON)
+    }
+}
+
+/// Quaternion representing rotation
+///
+/// Unlike Euler angles (`mat_rot_x`, `mat_rot_y`, `mat_rot_z`)
+/// quaternions do not suffer from gimbal lock and can be
+/// smoothly interpolated
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Quat {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quat {
+    pub fn new(w: f32, x: f32, y: f32, z: f32) -> Quat {
+        Quat { w, x, y, z }
+    }
+
+    pub fn id() -> Quat {
+        Quat::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// Rotation about `axis` by `rad` radians
+    ///
+    /// Same rotation as `Mat4x4::mat_rot_axis`
+    ///
+    pub fn from_axis_angle(axis: &draw_3d::Vec3D, rad: &f32) -> Quat {
+        let k = axis.normalize();
+        let half = rad * 0.5;
+        let s = half.sin();
+
+        Quat::new(half.cos(), k.x * s, k.y * s, k.z * s)
+    }
+
+    /// Extracts rotation from upper 3x3 part of the matrix
+    ///
+    /// Matrix is expected to be pure rotation (no scale or shear)
+    ///
+    pub fn from_mat4x4(m: &Mat4x4) -> Quat {
+        let m = &m.m;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quat::new(
+                0.25 * s,
+                (m[1][2] - m[2][1]) / s,
+                (m[2][0] - m[0][2]) / s,
+                (m[0][1] - m[1][0]) / s,
+            )
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+            Quat::new(
+                (m[1][2] - m[2][1]) / s,
+                0.25 * s,
+                (m[1][0] + m[0][1]) / s,
+                (m[2][0] + m[0][2]) / s,
+            )
+        } else if m[1][1] > m[2][2] {
+            let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+            Quat::new(
+                (m[2][0] - m[0][2]) / s,
+                (m[1][0] + m[0][1]) / s,
+                0.25 * s,
+                (m[2][1] + m[1][2]) / s,
+            )
+        } else {
+            let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+            Quat::new(
+                (m[0][1] - m[1][0]) / s,
+                (m[2][0] + m[0][2]) / s,
+                (m[2][1] + m[1][2]) / s,
+                0.25 * s,
+            )
+        };
+
+        q.normalize()
+    }
+
+    /// Converts quaternion into rotation matrix
+    ///
+    pub fn to_mat4x4(&self) -> Mat4x4 {
+        let Quat { w, x, y, z } = self.normalize();
+        let mut m = Mat4x4::id();
+
+        m.m[0][0] = 1.0 - 2.0 * (y * y + z * z);
+        m.m[0][1] = 2.0 * (x * y + w * z);
+        m.m[0][2] = 2.0 * (x * z - w * y);
+        m.m[1][0] = 2.0 * (x * y - w * z);
+        m.m[1][1] = 1.0 - 2.0 * (x * x + z * z);
+        m.m[1][2] = 2.0 * (y * z + w * x);
+        m.m[2][0] = 2.0 * (x * z + w * y);
+        m.m[2][1] = 2.0 * (y * z - w * x);
+        m.m[2][2] = 1.0 - 2.0 * (x * x + y * y);
+        m
+    }
+
+    /// Hamilton product
+    ///
+    /// `a.mul(&b)` applies `b` first and `a` second, so
+    /// `a.mul(&b).to_mat4x4()` equals `b.to_mat4x4().mul(&a.to_mat4x4())`
+    ///
+    pub fn mul(&self, other: &Quat) -> Quat {
+        Quat::new(
+            self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        )
+    }
+
+    pub fn conjugate(&self) -> Quat {
+        Quat::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn dot(&self, other: &Quat) -> f32 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn len(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Quat {
+        let l = self.len();
+
+        Quat::new(self.w / l, self.x / l, self.y / l, self.z / l)
+    }
+
+    /// Rotates vector by this quaternion
+    ///
+    pub fn rotate(&self, v: &draw_3d::Vec3D) -> draw_3d::Vec3D {
+        let p = Quat::new(0.0, v.x, v.y, v.z);
+        let r = Quat::mul(&self.mul(&p), &self.conjugate());
+
+        draw_3d::Vec3D::new(r.x, r.y, r.z)
+    }
+
+    /// Normalized linear interpolation
+    ///
+    /// Cheaper than `slerp` but does not keep constant angular speed
+    ///
+    pub fn nlerp(&self, other: &Quat, t: f32) -> Quat {
+        // Take the shorter path around the hypersphere
+        let sign = if self.dot(other) < 0.0 { -1.0 } else { 1.0 };
+
+        Quat::new(
+            self.w + (sign * other.w - self.w) * t,
+            self.x + (sign * other.x - self.x) * t,
+            self.y + (sign * other.y - self.y) * t,
+            self.z + (sign * other.z - self.z) * t,
+        )
+        .normalize()
+    }
+
+    /// Spherical linear interpolation
+    ///
+    pub fn slerp(&self, other: &Quat, t: f32) -> Quat {
+        let mut cos_theta = self.dot(other);
+        let mut end = *other;
+
+        if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            end = Quat::new(-other.w, -other.x, -other.y, -other.z);
+        }
+
+        // Nearly parallel quaternions, sin(theta) would be close to zero
+        if cos_theta > 1.0 - EPSILON {
+            return self.nlerp(&end, t);
+        }
+
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin_theta;
+        let b = (t * theta).sin() / sin_theta;
+
+        Quat::new(
+            a * self.w + b * end.w,
+            a * self.x + b * end.x,
+            a * self.y + b * end.y,
+            a * self.z + b * end.z,
+        )
+    }
+}
+
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, other: Quat) -> Quat {
+        Quat::mul(&self, &other)
+    }
+}
+
+/// Quaternions are equal when they represent the same rotation
+///
+impl PartialEq for Quat {
+    fn eq(&self, other: &Quat) -> bool {
+        1.0 - self.normalize().dot(&other.normalize()).abs() <= EPSILON
+    }
 }
 
 /// 4x4 Matrix multiplication function
 /// Multiplies 3D vector over 4x4 matrix (fourth value is implied 1)
 ///
+/// Resulting fourth value is dropped, use `mult_matrix_vec4`
+/// for projection matrices
+///
 pub fn mult_matrix_vector(i: &draw_3d::Vec3D, m: &Mat4x4) -> draw_3d::Vec3D {
+    mult_matrix_vec4(&draw_3d::Vec4D::from_point(i), m).xyz()
+}
+
+/// Multiplies 3D direction over 4x4 matrix (fourth value is implied 0)
+///
+/// Translation part of the matrix is ignored
+///
+pub fn mult_matrix_direction(i: &draw_3d::Vec3D, m: &Mat4x4) -> draw_3d::Vec3D {
+    mult_matrix_vec4(&draw_3d::Vec4D::from_direction(i), m).xyz()
+}
+
+/// Multiplies many points (fourth value is implied 1) over
+/// 4x4 matrix, uses SIMD when CPU supports it
+///
+pub fn mult_matrix_points(points: &[draw_3d::Vec3D], m: &Mat4x4) -> Vec<draw_3d::Vec4D> {
+    simd::transform_points(simd::Backend::detect(), points, m)
+}
+
+/// Multiplies homogeneous 4D vector over 4x4 matrix
+///
+pub fn mult_matrix_vec4(i: &draw_3d::Vec4D, m: &Mat4x4) -> draw_3d::Vec4D {
     let x = (i.x * m.m[0][0]) + (i.y * m.m[1][0]) + (i.z * m.m[2][0]) + (i.w * m.m[3][0]);
     let y = (i.x * m.m[0][1]) + (i.y * m.m[1][1]) + (i.z * m.m[2][1]) + (i.w * m.m[3][1]);
     let z = (i.x * m.m[0][2]) + (i.y * m.m[1][2]) + (i.z * m.m[2][2]) + (i.w * m.m[3][2]);
     let w = (i.x * m.m[0][3]) + (i.y * m.m[1][3]) + (i.z * m.m[2][3]) + (i.w * m.m[3][3]);
 
-    draw_3d::Vec3D { x, y, z, w }
+    draw_3d::Vec4D { x, y, z, w }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_3d::Vec3D;
+
+    fn products() -> Vec<Mat4x4> {
+        let rot = Mat4x4::mat_rot_axis(&Vec3D::new(1.0, 2.0, 3.0), &0.7);
+        let scale = Mat4x4::mat_scale(2.0, 0.5, 3.0);
+        let trans = Mat4x4::mat_trans(1.0, -2.0, 5.0);
+        let proj = Mat4x4::mat_proj(90.0, 0.5, 1000.0, 0.1);
+        vec![rot, scale, trans, rot * scale * trans, rot * trans * proj]
+    }
+
+    #[test]
+    fn inverse_of_products() {
+        for m in products() {
+            let inverse = m.inverse().unwrap();
+            assert!((m * inverse).approx_eq(&Mat4x4::id(), 1e-4), "{:?}", m);
+            assert!((inverse * m).approx_eq(&Mat4x4::id(), 1e-4), "{:?}", m);
+        }
+    }
+
+    #[test]
+    fn inverse_of_small_scale() {
+        // Determinant is 1e-9
+        let m = Mat4x4::mat_scale(0.001, 0.001, 0.001) * Mat4x4::mat_rot_x(&0.4);
+        let inverse = m.inverse().unwrap();
+        assert!((m * inverse).approx_eq(&Mat4x4::id(), 1e-4));
+        assert!((inverse[0][0] - 1000.0).abs() < 1e-1);
+    }
+
+    #[test]
+    fn inverse_of_singular() {
+        assert_eq!(Mat4x4::mat_scale(1.0, 0.0, 1.0).inverse(), None);
+        assert_eq!(Mat4x4::new_empty().inverse(), None);
+
+        let mut m = Mat4x4::id();
+        m[1] = m[0];
+        assert_eq!(m.inverse(), None);
+    }
+
+    #[test]
+    fn determinant() {
+        assert!((Mat4x4::mat_scale(2.0, 3.0, 4.0).determinant() - 24.0).abs() < EPSILON);
+        assert!((Mat4x4::mat_trans(5.0, 6.0, 7.0).determinant() - 1.0).abs() < EPSILON);
+        for angle in &[0.0, 0.3, 1.0, 2.5] {
+            for m in &[
+                Mat4x4::mat_rot_x(angle),
+                Mat4x4::mat_rot_y(angle),
+                Mat4x4::mat_rot_z(angle),
+            ] {
+                assert!((m.determinant() - 1.0).abs() < EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn transpose_twice() {
+        for m in products() {
+            assert_eq!(m.transpose().transpose(), m);
+        }
+        let m = Mat4x4::mat_trans(1.0, 2.0, 3.0).transpose();
+        assert_eq!(m.m[0][3], 1.0);
+        assert_eq!(m.m[3][0], 0.0);
+    }
+
+    #[test]
+    fn rotation_about_axis() {
+        for angle in &[0.0, 0.3, 1.0, 2.5, -1.2] {
+            assert_eq!(
+                Mat4x4::mat_rot_axis(&Vec3D::new(1.0, 0.0, 0.0), angle),
+                Mat4x4::mat_rot_x(angle)
+            );
+            assert_eq!(
+                Mat4x4::mat_rot_axis(&Vec3D::new(0.0, 0.0, 2.0), angle),
+                Mat4x4::mat_rot_z(angle)
+            );
+            assert_eq!(
+                Mat4x4::mat_rot_axis(&Vec3D::new(0.0, 1.0, 0.0), angle),
+                Mat4x4::mat_rot_y(&-angle)
+            );
+        }
+    }
+
+    #[test]
+    fn index() {
+        let mut m = Mat4x4::mat_trans(1.0, 2.0, 3.0);
+        assert_eq!(m[3][1], 2.0);
+        m[0][2] = 7.0;
+        assert_eq!(m.m[0][2], 7.0);
+        m[2] = [1.0, 1.0, 1.0, 1.0];
+        assert_eq!(m.m[2], [1.0; 4]);
+    }
+
+    #[test]
+    fn approximate_equality() {
+        let m = Mat4x4::mat_rot_x(&0.5);
+        let mut close = m;
+        close[1][1] += EPSILON * 0.5;
+        let mut far = m;
+        far[1][1] += EPSILON * 10.0;
+
+        assert_eq!(m, close);
+        assert_ne!(m, far);
+        assert!(m.approx_eq(&far, EPSILON * 20.0));
+    }
+
+    fn assert_vec_close(a: Vec3D, b: Vec3D) {
+        assert!((a - b).len() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    /// Rotations covering every branch of `Quat::from_mat4x4`,
+    /// half turns have w = 0 and negative trace
+    ///
+    fn rotations() -> Vec<Quat> {
+        let mut rotations = vec![Quat::id()];
+        for axis in &[
+            Vec3D::new(1.0, 0.0, 0.0),
+            Vec3D::new(0.0, 1.0, 0.0),
+            Vec3D::new(0.0, 0.0, 1.0),
+            Vec3D::new(1.0, -2.0, 0.5),
+        ] {
+            for angle in &[0.4, 2.0, 3.0, std::f32::consts::PI, -2.8] {
+                rotations.push(Quat::from_axis_angle(axis, angle));
+            }
+        }
+        rotations
+    }
+
+    #[test]
+    fn quat_matrix_round_trip() {
+        for q in rotations() {
+            let m = q.to_mat4x4();
+            let back = Quat::from_mat4x4(&m);
+            assert_eq!(back, q, "{:?}", m);
+            assert!((back.len() - 1.0).abs() < EPSILON);
+            assert_eq!(back.to_mat4x4(), m);
+        }
+
+        let half_turn = Quat::from_axis_angle(&Vec3D::new(0.0, 0.0, 1.0), &std::f32::consts::PI);
+        assert!(half_turn.w.abs() < EPSILON);
+        assert!(half_turn.to_mat4x4().determinant() > 0.0);
+    }
+
+    #[test]
+    fn quat_matches_axis_rotation() {
+        let v = Vec3D::new(0.3, -1.0, 2.0);
+        for axis in &[Vec3D::new(0.0, 1.0, 0.0), Vec3D::new(-1.0, 2.0, 3.0)] {
+            for angle in &[0.0, 0.5, -1.3, 2.9] {
+                let q = Quat::from_axis_angle(axis, angle);
+                let m = Mat4x4::mat_rot_axis(axis, angle);
+                assert_eq!(q.to_mat4x4(), m);
+                assert_vec_close(q.rotate(&v), mult_matrix_vector(&v, &m));
+            }
+        }
+    }
+
+    #[test]
+    fn quat_product_order() {
+        let a = Quat::from_axis_angle(&Vec3D::new(1.0, 0.0, 0.0), &0.7);
+        let b = Quat::from_axis_angle(&Vec3D::new(0.0, 1.0, 1.0), &-1.1);
+        assert_eq!((a * b).to_mat4x4(), b.to_mat4x4() * a.to_mat4x4());
+    }
+
+    #[test]
+    fn slerp_endpoints() {
+        let rotations = rotations();
+        for pair in rotations.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.slerp(&b, 0.0), a);
+            assert_eq!(a.slerp(&b, 1.0), b);
+        }
+    }
+
+    #[test]
+    fn slerp_shortest_path() {
+        let axis = Vec3D::new(0.0, 0.0, 1.0);
+        let a = Quat::id();
+        let b = Quat::from_axis_angle(&axis, &2.0);
+        // Same rotation as `b`, on the far side of the hypersphere
+        let far_b = Quat::new(-b.w, -b.x, -b.y, -b.z);
+        assert!(a.dot(&far_b) < 0.0);
+
+        let half = Quat::from_axis_angle(&axis, &1.0);
+        assert_eq!(a.slerp(&far_b, 0.5), half);
+        assert_eq!(a.nlerp(&far_b, 0.5), half);
+        assert_eq!(a.slerp(&far_b, 0.25), Quat::from_axis_angle(&axis, &0.5));
+    }
+
+    #[test]
+    fn nlerp_matches_slerp() {
+        let a = Quat::from_axis_angle(&Vec3D::new(1.0, 1.0, 0.0), &0.3);
+        for b in &[
+            Quat::from_axis_angle(&Vec3D::new(0.0, -1.0, 2.0), &2.4),
+            Quat::from_axis_angle(&Vec3D::new(1.0, 1.0, 0.0), &-2.9),
+        ] {
+            for &t in &[0.0, 0.5, 1.0] {
+                let slerp = a.slerp(b, t);
+                assert_eq!(a.nlerp(b, t), slerp, "t = {}", t);
+                assert!((slerp.len() - 1.0).abs() < EPSILON);
+            }
+        }
+    }
 }